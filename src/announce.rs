@@ -0,0 +1,365 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::config::{Config, DestinationKind, PayloadFormat, WebhookConfig};
+use crate::formatter::{self, SessionContext};
+use crate::git::{self, GitInfo};
+use crate::hook::{self, DryRun, HookInput};
+use crate::redact::Redactor;
+use crate::session::{SessionState, SessionStore};
+
+/// 会話とは別にチャンネルへ投稿する内容の種類
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnouncementKind {
+    Started,
+    Stopped,
+    /// `/aloud-code:note` のメモ
+    Note,
+}
+
+/// トグルhookから告知用のプロセスに渡す内容（終了時は状態が消えているので、必要な値をすべて持たせる）
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Announcement {
+    pub kind: AnnouncementKind,
+    pub session_id: String,
+    pub cwd: String,
+    pub model: Option<String>,
+    /// 開始時はONにした直後の、終了時はOFFにする直前の状態
+    pub state: SessionState,
+    /// 終了した理由（`/aloud-code:off` 以外で終わった場合）
+    pub reason: Option<String>,
+    /// メモの本文（マスク前）
+    pub text: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+impl Announcement {
+    pub fn new(kind: AnnouncementKind, input: &HookInput, state: SessionState) -> Self {
+        Announcement {
+            kind,
+            session_id: input.session_id.clone().unwrap_or_default(),
+            cwd: input.cwd.clone().unwrap_or_default(),
+            model: input.model.clone(),
+            state,
+            reason: None,
+            text: None,
+            at: Utc::now(),
+        }
+    }
+
+    pub fn with_reason(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+
+    pub fn with_text(mut self, text: &str) -> Self {
+        self.text = Some(text.to_string());
+        self
+    }
+
+    /// 告知するか（設定で有効にしていて、プレビュー中でない）
+    pub fn enabled(config: &Config, state: &SessionState) -> bool {
+        config.sessions.announce && !state.preview
+    }
+
+    /// 告知を別プロセスで送る。トグルhookのタイムアウトに影響しないよう終了を待たない
+    pub fn spawn(&self) -> Result<()> {
+        let mut child = Command::new(std::env::current_exe()?)
+            .args(["hook", "announce"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(&serde_json::to_vec(self)?)?;
+        }
+        Ok(())
+    }
+
+    fn session_context(&self, config: &Config) -> SessionContext {
+        SessionContext {
+            session_id: self.session_id.clone(),
+            cwd: self.cwd.clone(),
+            model: self.model.clone(),
+            labels: config.labels.clone(),
+            title: self.state.title.clone(),
+            tags: self.state.tags.clone(),
+        }
+    }
+
+    /// Slack形式のペイロード
+    pub fn payload(&self, ctx: &SessionContext, git: Option<&GitInfo>, who: Option<&str>) -> Value {
+        let mut blocks = Vec::new();
+        match self.kind {
+            AnnouncementKind::Started => {
+                blocks.push(section(":red_circle: *Streaming started*"));
+                let mut details = vec![format!("*project* {}", ctx.project_name())];
+                if let Some(branch) = git.and_then(|g| g.branch.as_deref()) {
+                    details.push(format!("*branch* {}", branch));
+                }
+                if let Some(model) = &ctx.model {
+                    details.push(format!("*model* {}", model));
+                }
+                if let Some(who) = who {
+                    details.push(format!("*by* {}", who));
+                }
+                if let Some(expires_at) = self.state.expires_at {
+                    details.push(format!("*until* {}", expires_at.format("%H:%M UTC")));
+                }
+                blocks.push(json!({
+                    "type": "context",
+                    "elements": [{"type": "mrkdwn", "text": details.join(" · ")}]
+                }));
+            }
+            AnnouncementKind::Stopped => {
+                let mut text = format!(
+                    ":white_circle: *Streaming stopped* · {} prompt{}, {} response{}",
+                    self.state.prompts,
+                    plural(self.state.prompts),
+                    self.state.responses,
+                    plural(self.state.responses)
+                );
+                if let Some(started) = self.state.activated_at {
+                    text.push_str(&format!(" in {}", duration(self.at - started)));
+                }
+                if let Some(reason) = &self.reason {
+                    text.push_str(&format!(" ({})", reason));
+                }
+                blocks.push(section(&text));
+            }
+            AnnouncementKind::Note => {
+                return formatter::format_note(self.text.as_deref().unwrap_or(""), who, ctx);
+            }
+        }
+        json!({
+            "username": ctx.username(),
+            "blocks": blocks
+        })
+    }
+}
+
+fn section(text: &str) -> Value {
+    json!({
+        "type": "section",
+        "text": {"type": "mrkdwn", "text": text}
+    })
+}
+
+pub(crate) fn plural(count: u64) -> &'static str {
+    if count == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// `1h 05m` / `12m` / `45s`
+fn duration(duration: chrono::Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    match (seconds / 3600, seconds / 60 % 60) {
+        (0, 0) => format!("{}s", seconds),
+        (0, m) => format!("{}m", m),
+        (h, m) => format!("{}h {:02}m", h, m),
+    }
+}
+
+/// 告知を送る送信先（Slack形式でテンプレートを使わないWebhookのみ）
+pub(crate) fn targets<'a>(
+    config: &'a Config,
+    state: &SessionState,
+) -> Vec<(&'a str, &'a WebhookConfig)> {
+    config
+        .destinations()
+        .into_iter()
+        .filter(|(name, _)| {
+            state
+                .destinations
+                .as_ref()
+                .is_none_or(|names| names.iter().any(|n| n == name))
+        })
+        .filter(|(_, w)| {
+            w.kind == DestinationKind::Webhook
+                && w.format == PayloadFormat::Slack
+                && w.template.is_none()
+        })
+        .collect()
+}
+
+/// 操作している人（gitのuser.name、なければログインユーザー名）
+fn who(cwd: &str) -> Option<String> {
+    git::user_name(cwd).or_else(|| std::env::var("USER").ok().filter(|u| !u.is_empty()))
+}
+
+/// `aloud-code hook announce`: 標準入力で受け取った告知を送る
+pub async fn handle_announce() -> Result<()> {
+    let mut buf = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut buf)?;
+    let mut announcement: Announcement = serde_json::from_str(&buf)?;
+
+    let config = Config::load_for(Some(Path::new(&announcement.cwd)))?;
+    config.validate()?;
+    if let Some(text) = &mut announcement.text {
+        *text = Redactor::new(&config.redact)?.redact(text).into_owned();
+    }
+    let targets = targets(&config, &announcement.state);
+    if targets.is_empty() {
+        return Ok(());
+    }
+    let ctx = announcement.session_context(&config);
+    let git = GitInfo::detect(&ctx.cwd);
+    let payload = announcement.payload(&ctx, git.as_ref(), who(&ctx.cwd).as_deref());
+
+    let dry_run = DryRun::for_session(&announcement.session_id, &announcement.state)?;
+    let mut last_err = None;
+    for (name, webhook) in targets {
+        let payload = formatter::with_channel(payload.clone(), webhook.channel.as_deref());
+        if let Some(dry_run) = &dry_run {
+            dry_run.write(&format!(
+                "--- announce {:?} → {}\n{}\n",
+                announcement.kind,
+                name,
+                serde_json::to_string_pretty(&payload)?
+            ))?;
+            continue;
+        }
+        let result = match hook::sender_for(&config.http, webhook) {
+            Ok(sender) => sender.send(payload).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("aloud-code: 送信先 {} への告知に失敗: {}", name, e);
+            // 告知のプロセスの標準エラー出力は捨てているので、/aloud-code:status で見られるようにする
+            let recorded = SessionStore::open().and_then(|store| {
+                store.record_failure(&announcement.session_id, name, &format!("{:#}", e))
+            });
+            if let Err(e) = recorded {
+                eprintln!("aloud-code: 送信の失敗を記録できません: {}", e);
+            }
+            last_err = Some(e);
+        }
+    }
+    match last_err {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn announcement(kind: AnnouncementKind, state: SessionState) -> Announcement {
+        Announcement {
+            kind,
+            session_id: "abcdef12-3456".to_string(),
+            cwd: "/home/user/my-project".to_string(),
+            model: Some("claude-sonnet-4-6".to_string()),
+            state,
+            reason: None,
+            text: None,
+            at: Utc.with_ymd_and_hms(2026, 1, 2, 4, 5, 0).unwrap(),
+        }
+    }
+
+    fn ctx(announcement: &Announcement) -> SessionContext {
+        announcement.session_context(&Config::default())
+    }
+
+    #[test]
+    fn test_started_payload() {
+        let a = announcement(AnnouncementKind::Started, SessionState::default());
+        let git = GitInfo {
+            branch: Some("main".to_string()),
+            ..Default::default()
+        };
+        let payload = a.payload(&ctx(&a), Some(&git), Some("alice"));
+        assert_eq!(payload["username"], "my-project [abcdef12]");
+        assert_eq!(
+            payload["blocks"][0]["text"]["text"],
+            ":red_circle: *Streaming started*"
+        );
+        assert_eq!(
+            payload["blocks"][1]["elements"][0]["text"],
+            "*project* my-project · *branch* main · *model* claude-sonnet-4-6 · *by* alice"
+        );
+    }
+
+    #[test]
+    fn test_stopped_payload() {
+        let state = SessionState {
+            activated_at: Some(Utc.with_ymd_and_hms(2026, 1, 2, 3, 0, 0).unwrap()),
+            prompts: 1,
+            responses: 3,
+            ..Default::default()
+        };
+        let a = announcement(AnnouncementKind::Stopped, state).with_reason("time limit reached");
+        let payload = a.payload(&ctx(&a), None, None);
+        assert_eq!(
+            payload["blocks"][0]["text"]["text"],
+            ":white_circle: *Streaming stopped* · 1 prompt, 3 responses in 1h 05m (time limit reached)"
+        );
+        assert_eq!(payload["blocks"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_note_payload() {
+        let a = announcement(AnnouncementKind::Note, SessionState::default())
+            .with_text("Pausing for lunch");
+        let payload = a.payload(&ctx(&a), None, Some("alice"));
+        assert_eq!(
+            payload["blocks"][0]["text"]["text"],
+            ":memo: *Note from alice*\nPausing for lunch"
+        );
+    }
+
+    #[test]
+    fn test_targets() {
+        let config = Config::parse(
+            r#"
+[webhook]
+url = "https://hooks.slack.com/services/x"
+
+[destinations.review]
+url = "https://hooks.slack.com/services/y"
+
+[destinations.ingest]
+url = "https://example.com/events"
+format = "event"
+
+[destinations.discord]
+url = "https://example.com/discord"
+template = "discord"
+
+[destinations.log]
+type = "archive"
+"#,
+        )
+        .unwrap();
+        let names = |state: &SessionState| -> Vec<&str> {
+            targets(&config, state)
+                .into_iter()
+                .map(|(n, _)| n)
+                .collect()
+        };
+        assert_eq!(names(&SessionState::default()), vec!["default", "review"]);
+        let state = SessionState {
+            destinations: Some(vec!["review".to_string(), "ingest".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(names(&state), vec!["review"]);
+    }
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(duration(chrono::Duration::seconds(42)), "42s");
+        assert_eq!(duration(chrono::Duration::minutes(12)), "12m");
+        assert_eq!(duration(chrono::Duration::minutes(65)), "1h 05m");
+    }
+}
@@ -0,0 +1,322 @@
+use anyhow::Result;
+use chrono::{Days, NaiveDate};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::event::EventEnvelope;
+use crate::formatter::Message;
+
+/// 日付ディレクトリ名の形式
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// アーカイブの保存期間と容量の上限
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Retention {
+    pub max_age_days: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+/// アーカイブの保存先（`<state_dir>/archive`）
+pub fn archive_dir() -> Result<PathBuf> {
+    Ok(config::state_dir()?.join("archive"))
+}
+
+/// イベントエンベロープを `<dir>/<日付>/<セッションID>.jsonl` に1行追記し、そのパスを返す
+pub fn append(dir: &Path, message: &Message) -> Result<PathBuf> {
+    let envelope = EventEnvelope::new(message);
+    let day_dir = dir.join(message.timestamp.format(DATE_FORMAT).to_string());
+    fs::create_dir_all(&day_dir)?;
+    let path = day_dir.join(format!("{}.jsonl", file_stem(&envelope.session_id)));
+
+    let mut line = serde_json::to_vec(&envelope)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    // 並行して動くhookプロセスの行が混ざらないよう、1行を排他ロック中に書き切る
+    file.lock()?;
+    file.write_all(&line)?;
+    file.sync_data()?;
+    Ok(path)
+}
+
+/// 保存期間を過ぎた日付ディレクトリと、容量を超えた分の古いファイルを削除する。`keep` は削除しない
+pub fn prune(dir: &Path, retention: Retention, today: NaiveDate, keep: &Path) -> Result<()> {
+    let mut days = date_dirs(dir)?;
+    if let Some(cutoff) = retention
+        .max_age_days
+        .and_then(|d| today.checked_sub_days(Days::new(d)))
+    {
+        for (_, path) in days.iter().filter(|(date, _)| *date < cutoff) {
+            remove(fs::remove_dir_all(path))?;
+        }
+        days.retain(|(date, _)| *date >= cutoff);
+    }
+
+    if let Some(max_bytes) = retention.max_bytes {
+        // 古い日付の、更新が古いファイルから消す
+        let mut files = Vec::new();
+        for (date, day_dir) in &days {
+            for entry in fs::read_dir(day_dir)? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                if metadata.is_file() {
+                    files.push((*date, metadata.modified()?, metadata.len(), entry.path()));
+                }
+            }
+        }
+        files.sort();
+        let mut total: u64 = files.iter().map(|(_, _, len, _)| len).sum();
+        for (_, _, len, path) in &files {
+            if total <= max_bytes {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+            remove(fs::remove_file(path))?;
+            total = total.saturating_sub(*len);
+        }
+        for (_, day_dir) in &days {
+            // 空になった日付ディレクトリを片付ける（空でなければ失敗するので無視する）
+            let _ = fs::remove_dir(day_dir);
+        }
+    }
+    Ok(())
+}
+
+/// アーカイブからセッションのイベントを日付順に読み込む
+pub fn read_session(dir: &Path, session_id: &str) -> Result<Vec<EventEnvelope>> {
+    let file_name = format!("{}.jsonl", file_stem(session_id));
+    let mut events = Vec::new();
+    for (_, day_dir) in date_dirs(dir)? {
+        let path = day_dir.join(&file_name);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            events.push(
+                serde_json::from_str(line)
+                    .map_err(|e| anyhow::anyhow!("{} の読み込みに失敗: {}", path.display(), e))?,
+            );
+        }
+    }
+    Ok(events)
+}
+
+/// 日付として読めるサブディレクトリを古い順に返す
+fn date_dirs(dir: &Path) -> Result<Vec<(NaiveDate, PathBuf)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut days = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        if let Ok(date) = NaiveDate::parse_from_str(&name.to_string_lossy(), DATE_FORMAT) {
+            if entry.file_type()?.is_dir() {
+                days.push((date, entry.path()));
+            }
+        }
+    }
+    days.sort();
+    Ok(days)
+}
+
+/// 別のhookプロセスが先に消した場合は成功とみなす
+fn remove(result: std::io::Result<()>) -> Result<()> {
+    match result {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// セッションIDをファイル名に使える形にする
+pub(crate) fn file_stem(session_id: &str) -> String {
+    let stem: String = session_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if stem.is_empty() {
+        "unknown".to_string()
+    } else {
+        stem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatter::{MessageKind, SessionContext};
+    use chrono::{TimeZone, Utc};
+
+    fn ctx(session_id: &str) -> SessionContext {
+        SessionContext {
+            session_id: session_id.to_string(),
+            cwd: "/home/user/my-project".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn message<'a>(ctx: &'a SessionContext, text: &'a str, sequence: u64) -> Message<'a> {
+        Message {
+            kind: MessageKind::User,
+            text,
+            ctx,
+            timestamp: Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap(),
+            sequence,
+            git: None,
+        }
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, DATE_FORMAT).unwrap()
+    }
+
+    fn write(path: &Path, len: usize) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "x".repeat(len)).unwrap();
+    }
+
+    #[test]
+    fn test_append_writes_one_envelope_per_line() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let ctx = ctx("abc123");
+        append(temp_dir.path(), &message(&ctx, "first", 1)).unwrap();
+        let path = append(temp_dir.path(), &message(&ctx, "second", 2)).unwrap();
+        assert_eq!(path, temp_dir.path().join("2026-01-02/abc123.jsonl"));
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<EventEnvelope> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).expect("1行が1つのエンベロープになっていない"))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].content.text, "first");
+        assert_eq!(lines[1].sequence, 2);
+    }
+
+    #[test]
+    fn test_concurrent_appends_do_not_interleave() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let dir = temp_dir.path().to_path_buf();
+        let text = "y".repeat(64 * 1024);
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let dir = dir.clone();
+                let text = text.clone();
+                std::thread::spawn(move || {
+                    let ctx = ctx("shared");
+                    append(&dir, &message(&ctx, &text, i)).unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let content = fs::read_to_string(dir.join("2026-01-02/shared.jsonl")).unwrap();
+        assert_eq!(content.lines().count(), 8);
+        for line in content.lines() {
+            serde_json::from_str::<EventEnvelope>(line).expect("行が混ざっている");
+        }
+    }
+
+    #[test]
+    fn test_prune_by_age() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let dir = temp_dir.path();
+        write(&dir.join("2026-01-01/old.jsonl"), 10);
+        write(&dir.join("2026-01-08/kept.jsonl"), 10);
+        write(&dir.join("2026-01-10/today.jsonl"), 10);
+        write(&dir.join("notes/other.txt"), 10);
+
+        let retention = Retention {
+            max_age_days: Some(7),
+            max_bytes: None,
+        };
+        let keep = dir.join("2026-01-10/today.jsonl");
+        prune(dir, retention, date("2026-01-10"), &keep).unwrap();
+        assert!(!dir.join("2026-01-01").exists(), "古い日付が残っている");
+        assert!(dir.join("2026-01-08/kept.jsonl").exists());
+        assert!(keep.exists());
+        assert!(
+            dir.join("notes/other.txt").exists(),
+            "日付以外のディレクトリを消した"
+        );
+    }
+
+    #[test]
+    fn test_prune_by_size_removes_oldest_first() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let dir = temp_dir.path();
+        write(&dir.join("2026-01-01/a.jsonl"), 100);
+        write(&dir.join("2026-01-02/b.jsonl"), 100);
+        write(&dir.join("2026-01-03/c.jsonl"), 100);
+
+        let retention = Retention {
+            max_age_days: None,
+            max_bytes: Some(250),
+        };
+        let keep = dir.join("2026-01-03/c.jsonl");
+        prune(dir, retention, date("2026-01-03"), &keep).unwrap();
+        assert!(
+            !dir.join("2026-01-01").exists(),
+            "空の日付ディレクトリが残っている"
+        );
+        assert!(dir.join("2026-01-02/b.jsonl").exists());
+        assert!(keep.exists());
+
+        // 書き込み中のファイルは上限を超えていても消さない
+        let retention = Retention {
+            max_age_days: None,
+            max_bytes: Some(10),
+        };
+        prune(dir, retention, date("2026-01-03"), &keep).unwrap();
+        assert!(!dir.join("2026-01-02").exists());
+        assert!(keep.exists());
+    }
+
+    #[test]
+    fn test_prune_missing_directory() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let missing = temp_dir.path().join("archive");
+        let retention = Retention {
+            max_age_days: Some(1),
+            max_bytes: Some(1),
+        };
+        assert!(prune(&missing, retention, date("2026-01-01"), &missing).is_ok());
+    }
+
+    #[test]
+    fn test_read_session_across_days() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let ctx = ctx("long-session");
+        let mut late = message(&ctx, "late", 2);
+        late.timestamp = Utc.with_ymd_and_hms(2026, 1, 3, 0, 0, 1).unwrap();
+        append(temp_dir.path(), &late).unwrap();
+        append(temp_dir.path(), &message(&ctx, "early", 1)).unwrap();
+
+        let events = read_session(temp_dir.path(), "long-session").unwrap();
+        let texts: Vec<&str> = events.iter().map(|e| e.content.text.as_str()).collect();
+        assert_eq!(texts, vec!["early", "late"]);
+        assert!(read_session(temp_dir.path(), "other").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(file_stem("abcdef12-3456"), "abcdef12-3456");
+        assert_eq!(file_stem("../etc/passwd"), "___etc_passwd");
+        assert_eq!(file_stem(""), "unknown");
+    }
+}
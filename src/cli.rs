@@ -0,0 +1,421 @@
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::archive;
+use crate::config::{self, Config, DestinationKind};
+use crate::formatter::{Message, MessageKind, SessionContext};
+use crate::git::GitInfo;
+use crate::hook;
+use crate::html;
+use crate::layers::EffectiveConfig;
+use crate::redact::Redactor;
+use crate::session::SessionStore;
+use crate::session_log::{self, SessionLog};
+use crate::templates::Templates;
+
+/// `aloud-code test` 用のサンプルセッション
+fn sample_session_context() -> SessionContext {
+    let cwd = std::env::current_dir()
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    SessionContext {
+        session_id: "aloud-code-test".to_string(),
+        cwd,
+        ..Default::default()
+    }
+}
+
+/// `aloud-code test`: 全送信先にテストメッセージを送り、結果を表示する
+pub async fn run_test() -> Result<()> {
+    let config = Config::load()?;
+    let destinations = config.destinations();
+    if destinations.is_empty() {
+        anyhow::bail!("送信先が設定されていません（config.tomlの[webhook] urlを確認してください）");
+    }
+
+    let mut ctx = sample_session_context();
+    ctx.labels = config.labels.clone();
+    let text = "This is a test message from `aloud-code test`.";
+    let templates = Templates::new(&config.templates)?;
+    let message = Message {
+        kind: MessageKind::User,
+        text,
+        ctx: &ctx,
+        timestamp: Utc::now(),
+        sequence: 1,
+        git: GitInfo::detect(&ctx.cwd),
+    };
+
+    let mut failures = 0;
+    for (name, webhook) in destinations {
+        println!("[{}]", name);
+        // ローカルファイルにテストメッセージは残さない
+        match webhook.kind {
+            DestinationKind::Webhook => {}
+            DestinationKind::Archive => {
+                println!(
+                    "  skipped: local archive ({})",
+                    archive::archive_dir()?.display()
+                );
+                continue;
+            }
+            DestinationKind::Markdown => {
+                println!(
+                    "  skipped: local transcript ({})",
+                    webhook.transcript_dir()?.display()
+                );
+                continue;
+            }
+        }
+        let prepared = hook::build_payload(webhook, &message, &templates)
+            .and_then(|payload| Ok((payload, hook::sender_for(&config.http, webhook)?)));
+        let (payload, sender) = match prepared {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                println!("  error:   {}", e);
+                failures += 1;
+                continue;
+            }
+        };
+        let header_names = sender.header_names();
+        if !header_names.is_empty() {
+            println!("  headers: {} (values hidden)", header_names.join(", "));
+        }
+        match sender.send_once(payload).await {
+            Ok(report) => {
+                println!("  status:  {}", report.status);
+                println!("  latency: {}ms", report.latency.as_millis());
+                println!("  body:    {}", report.body.trim());
+                if !report.status.is_success() {
+                    failures += 1;
+                }
+            }
+            Err(e) => {
+                println!("  error:   {}", e);
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        anyhow::bail!("{}件の送信先でテスト送信に失敗しました", failures);
+    }
+    Ok(())
+}
+
+/// `aloud-code replay` で送信する間隔のデフォルト（SlackのIncoming Webhookは1秒に1件程度まで）
+const DEFAULT_REPLAY_INTERVAL_MS: u64 = 1100;
+
+/// `aloud-code replay <transcript.jsonl|session-id> [--dry-run] [--interval-ms N]`:
+/// Claude Codeのセッションログから過去のセッションを送信する
+pub async fn replay(args: &[String]) -> Result<()> {
+    let mut target = None;
+    let mut dry_run = false;
+    let mut interval = Duration::from_millis(DEFAULT_REPLAY_INTERVAL_MS);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--interval-ms" => {
+                let value = iter
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| anyhow::anyhow!("--interval-ms にはミリ秒の数値が必要です"))?;
+                interval = Duration::from_millis(value);
+            }
+            other if other.starts_with("--") => anyhow::bail!("不明な引数: {}", other),
+            other => target = Some(other),
+        }
+    }
+    let target = target.ok_or_else(|| {
+        anyhow::anyhow!(
+            "usage: aloud-code replay <transcript.jsonl|session-id> [--dry-run] [--interval-ms N]"
+        )
+    })?;
+
+    let mut log = SessionLog::read(&session_log::locate(target)?)?;
+    let config = Config::load_for(Some(Path::new(&log.cwd)))?;
+    config.validate()?;
+    // 配信時に送らなかったプロンプトとその応答は再送しない
+    log.turns
+        .retain(|turn| !config.sessions.is_private(&turn.prompt));
+    let destinations = config.destinations();
+    if destinations.is_empty() {
+        anyhow::bail!("送信先が設定されていません（config.tomlの[webhook] urlを確認してください）");
+    }
+
+    let ctx = SessionContext {
+        session_id: log.session_id.clone(),
+        cwd: log.cwd.clone(),
+        model: log.model.clone(),
+        labels: config.labels.clone(),
+        ..Default::default()
+    };
+    let redactor = Redactor::new(&config.redact)?;
+    let templates = Templates::new(&config.templates)?;
+    let git = hook::detect_git(&destinations, &ctx.cwd);
+    let messages: Vec<(MessageKind, String, DateTime<Utc>)> = log
+        .turns
+        .iter()
+        .flat_map(|turn| {
+            std::iter::once((MessageKind::User, turn.prompt.as_str(), turn.prompt_at)).chain(
+                turn.response
+                    .as_deref()
+                    .zip(turn.response_at)
+                    .map(|(text, at)| (MessageKind::Assistant, text, at)),
+            )
+        })
+        .map(|(kind, text, at)| (kind, redactor.redact(text).into_owned(), at))
+        .collect();
+    if messages.is_empty() {
+        println!("送信するメッセージがありません");
+        return Ok(());
+    }
+
+    let mut failures = 0;
+    for (index, (kind, text, timestamp)) in messages.iter().enumerate() {
+        let message = Message {
+            kind: *kind,
+            text,
+            ctx: &ctx,
+            timestamp: *timestamp,
+            sequence: index as u64 + 1,
+            git: git.clone(),
+        };
+        if dry_run {
+            for (name, webhook) in &destinations {
+                print!("{}", hook::preview(name, webhook, &message, &templates)?);
+            }
+            continue;
+        }
+
+        if index > 0 {
+            tokio::time::sleep(interval).await;
+        }
+        for (name, webhook) in &destinations {
+            if let Err(e) = hook::deliver(&config, webhook, &message, &templates).await {
+                eprintln!("aloud-code: 送信先 {} への送信に失敗: {}", name, e);
+                failures += 1;
+            }
+        }
+        println!(
+            "[{}/{}] {} {}",
+            index + 1,
+            messages.len(),
+            message.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true),
+            kind.event_name()
+        );
+    }
+
+    if failures > 0 {
+        anyhow::bail!("{}件の送信に失敗しました", failures);
+    }
+    Ok(())
+}
+
+/// `aloud-code export --html <transcript.jsonl|session-id> [-o FILE]`:
+/// セッションを1つのHTMLファイルに書き出す。セッションログがなければローカルアーカイブから読む
+pub fn export(args: &[String]) -> Result<()> {
+    let usage = "usage: aloud-code export --html <transcript.jsonl|session-id> [-o FILE]";
+    let mut target = None;
+    let mut html = false;
+    let mut output = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--html" => html = true,
+            "-o" | "--output" => {
+                output = Some(
+                    iter.next()
+                        .ok_or_else(|| anyhow::anyhow!("{} には出力先のパスが必要です", arg))?,
+                );
+            }
+            other if other.starts_with('-') => anyhow::bail!("不明な引数: {}", other),
+            other => target = Some(other),
+        }
+    }
+    let target = target.ok_or_else(|| anyhow::anyhow!(usage))?;
+    if !html {
+        anyhow::bail!(
+            "出力形式を指定してください（現在は --html のみ）\n{}",
+            usage
+        );
+    }
+
+    let mut log = match session_log::locate(target) {
+        Ok(path) => SessionLog::read(&path)?,
+        Err(e) => {
+            let events = archive::read_session(&archive::archive_dir()?, target)?;
+            if events.is_empty() {
+                return Err(e.context("ローカルアーカイブにも記録がありません"));
+            }
+            SessionLog::from_envelopes(&events)
+        }
+    };
+    let config = Config::load_for(Some(Path::new(&log.cwd)))?;
+    log.turns
+        .retain(|turn| !config.sessions.is_private(&turn.prompt));
+    let redactor = Redactor::new(&config.redact)?;
+    let page = html::render(&log, &redactor);
+
+    let output = match output {
+        Some(path) => std::path::PathBuf::from(path),
+        None => {
+            let ctx = SessionContext {
+                session_id: log.session_id.clone(),
+                cwd: log.cwd.clone(),
+                ..Default::default()
+            };
+            std::path::PathBuf::from(format!(
+                "{}-{}.html",
+                archive::file_stem(ctx.project_name()),
+                archive::file_stem(ctx.short_id())
+            ))
+        }
+    };
+    std::fs::write(&output, page)
+        .map_err(|e| anyhow::anyhow!("{} に書き込めません: {}", output.display(), e))?;
+    println!("{}", output.display());
+    Ok(())
+}
+
+/// `aloud-code sessions prune [--days N]`: 操作のないまま残っているセッションの状態を削除する
+pub fn sessions_prune(args: &[String]) -> Result<()> {
+    let max_idle = match args {
+        [] => Config::load()?.sessions.max_idle(),
+        [flag, days] if flag == "--days" => {
+            let days: u32 = days
+                .parse()
+                .map_err(|_| anyhow::anyhow!("--days には日数が必要です"))?;
+            Some(chrono::Duration::days(days.into()))
+        }
+        _ => anyhow::bail!("usage: aloud-code sessions prune [--days N]"),
+    };
+    let Some(max_idle) = max_idle else {
+        println!("セッションの有効期限が設定されていません（[sessions] expire_after_days = 0）");
+        return Ok(());
+    };
+
+    let now = Utc::now();
+    let expired = SessionStore::open()?.prune(Some(max_idle), now)?;
+    for session in &expired {
+        println!(
+            "removed {} (last active {}, {} days ago)",
+            session.session_id,
+            session
+                .last_active
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            (now - session.last_active).num_days()
+        );
+    }
+    println!(
+        "{} session(s) inactive for more than {} days removed",
+        expired.len(),
+        max_idle.num_days()
+    );
+    Ok(())
+}
+
+/// `aloud-code config init [--url URL] [--force]`: 設定ファイルのテンプレートを書き出す
+pub fn config_init(args: &[String]) -> Result<()> {
+    let mut url = None;
+    let mut force = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--url" => {
+                url = Some(
+                    iter.next()
+                        .cloned()
+                        .ok_or_else(|| anyhow::anyhow!("--url には値が必要です"))?,
+                );
+            }
+            "--force" => force = true,
+            other => anyhow::bail!("不明な引数: {}", other),
+        }
+    }
+
+    let path = config::config_file_path()?;
+    if path.exists() && !force {
+        anyhow::bail!(
+            "{} は既に存在します（上書きするには --force を指定してください）",
+            path.display()
+        );
+    }
+
+    // 対話端末から実行された場合のみURLを尋ねる
+    if url.is_none() && std::io::stdin().is_terminal() {
+        print!("Webhook URL (空欄で後から設定): ");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        let line = line.trim();
+        if !line.is_empty() {
+            url = Some(line.to_string());
+        }
+    }
+
+    let content = config::config_template(url.as_deref());
+    Config::parse(&content)?.validate()?;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, content)?;
+    // Webhook URLは秘密情報なので本人のみ読めるようにする
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    println!("{} を作成しました", path.display());
+    Ok(())
+}
+
+/// `aloud-code config validate`: 全レイヤーの設定ファイルを検証し、問題を説明する
+pub fn config_validate() -> Result<()> {
+    let cwd = std::env::current_dir().ok();
+    let effective = EffectiveConfig::load(cwd.as_deref())?;
+    if effective.sources.is_empty() {
+        anyhow::bail!(
+            "{} が見つかりません（`aloud-code config init` で作成できます）",
+            config::config_file_path()?.display()
+        );
+    }
+    effective.config.validate()?;
+    for warning in &effective.warnings {
+        println!("警告: {}", warning);
+    }
+    for source in &effective.sources {
+        println!("{}: OK", source);
+    }
+    if effective.config.destinations().is_empty() {
+        println!("（ただし送信先URLが未設定です）");
+    }
+    Ok(())
+}
+
+/// `aloud-code config show [--effective]`: 読み込まれる設定レイヤー、またはマージ結果を表示する
+pub fn config_show(args: &[String]) -> Result<()> {
+    let effective_flag = match args.first().map(|s| s.as_str()) {
+        None => false,
+        Some("--effective") => true,
+        Some(other) => anyhow::bail!("不明な引数: {}", other),
+    };
+    let cwd = std::env::current_dir().ok();
+    let effective = EffectiveConfig::load(cwd.as_deref())?;
+    if effective_flag {
+        print!("{}", effective.render());
+    } else if effective.sources.is_empty() {
+        println!("設定ファイルがありません");
+    } else {
+        // 後に表示されるものほど優先される
+        for source in &effective.sources {
+            println!("{}", source);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::event::EventEnvelope;
+use crate::formatter::Message;
+use crate::webhook::Payload;
+
+/// CloudEvents仕様のバージョン
+pub const SPEC_VERSION: &str = "1.0";
+
+/// `data` のスキーマ（イベントエンベロープと同じ）
+const DATA_SCHEMA: &str =
+    "https://raw.githubusercontent.com/suzuki-toshihir0/aloud-code/main/schema/event.v1.json";
+
+/// 構造化モードで送るCloudEvent
+#[derive(Debug, Serialize)]
+pub struct CloudEvent {
+    pub specversion: &'static str,
+    pub id: String,
+    pub source: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub time: String,
+    pub datacontenttype: &'static str,
+    pub dataschema: &'static str,
+    pub data: EventEnvelope,
+}
+
+impl CloudEvent {
+    pub fn new(message: &Message) -> Self {
+        let data = EventEnvelope::new(message);
+        CloudEvent {
+            specversion: SPEC_VERSION,
+            id: event_id(&data),
+            source: format!(
+                "/aloud-code/{}/{}",
+                encode_segment(&data.project),
+                encode_segment(&data.session_id)
+            ),
+            event_type: format!("dev.aloud-code.{}", data.event_type),
+            time: data.timestamp.clone(),
+            datacontenttype: "application/json",
+            dataschema: DATA_SCHEMA,
+            data,
+        }
+    }
+
+    /// 構造化モード: イベント全体をボディにする
+    pub fn structured(self) -> Payload {
+        let body = serde_json::to_value(&self).unwrap_or(Value::Null);
+        Payload::from(body).with_header("content-type", "application/cloudevents+json")
+    }
+
+    /// バイナリモード: 属性を `ce-` ヘッダーに、`data` をボディにする
+    pub fn binary(self) -> Payload {
+        let body = serde_json::to_value(&self.data).unwrap_or(Value::Null);
+        Payload::from(body)
+            .with_header("content-type", self.datacontenttype)
+            .with_header("ce-specversion", self.specversion)
+            .with_header("ce-id", self.id)
+            .with_header("ce-source", self.source)
+            .with_header("ce-type", self.event_type)
+            .with_header("ce-time", self.time)
+            .with_header("ce-dataschema", self.dataschema)
+    }
+}
+
+/// イベントごとに一意なID（UUID v4形式）
+///
+/// 通し番号はONにし直したときやreplayで1から振り直されるため、`source` と組み合わせても
+/// 一意にならない（重複排除する受信側で後のイベントが捨てられる）。時刻・プロセス・呼び出し回数から作る
+fn event_id(data: &EventEnvelope) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let mut hasher = Sha256::new();
+    hasher.update(nanos.to_le_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update(COUNTER.fetch_add(1, Ordering::Relaxed).to_le_bytes());
+    hasher.update(data.session_id.as_bytes());
+    hasher.update(data.sequence.to_le_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&hasher.finalize()[..16]);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// URIのパスセグメントとして使えるようにパーセントエンコードする
+fn encode_segment(segment: &str) -> String {
+    let mut out = String::new();
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatter::{MessageKind, SessionContext};
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    fn ctx() -> SessionContext {
+        SessionContext {
+            session_id: "abc123".to_string(),
+            cwd: "/home/user/my project".to_string(),
+            model: Some("claude-sonnet-4-6".to_string()),
+            labels: Vec::new(),
+            ..Default::default()
+        }
+    }
+
+    fn event(ctx: &SessionContext, kind: MessageKind) -> CloudEvent {
+        CloudEvent::new(&Message {
+            kind,
+            text: "hello",
+            ctx,
+            timestamp: Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap(),
+            sequence: 3,
+            git: None,
+        })
+    }
+
+    fn header<'a>(payload: &'a Payload, name: &str) -> Option<&'a str> {
+        payload
+            .headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn is_uuid(id: &str) -> bool {
+        let parts: Vec<&str> = id.split('-').collect();
+        parts.iter().map(|p| p.len()).eq([8, 4, 4, 4, 12])
+            && parts
+                .iter()
+                .all(|p| p.chars().all(|c| c.is_ascii_hexdigit()))
+            && parts[2].starts_with('4')
+    }
+
+    #[test]
+    fn test_id_is_unique_for_same_sequence() {
+        // ONにし直したときやreplayでは同じ通し番号が再び使われる
+        let ctx = ctx();
+        let first = event(&ctx, MessageKind::User);
+        let second = event(&ctx, MessageKind::User);
+        assert_eq!(first.data.sequence, second.data.sequence);
+        assert_ne!(first.id, second.id);
+    }
+
+    #[test]
+    fn test_structured_mode() {
+        let ctx = ctx();
+        let payload = event(&ctx, MessageKind::User).structured();
+        assert_eq!(
+            header(&payload, "content-type"),
+            Some("application/cloudevents+json")
+        );
+        let body = &payload.body;
+        assert_eq!(body["specversion"], "1.0");
+        assert!(is_uuid(body["id"].as_str().unwrap()), "{}", body["id"]);
+        assert_eq!(body["source"], "/aloud-code/my%20project/abc123");
+        assert_eq!(body["type"], "dev.aloud-code.prompt");
+        assert_eq!(body["time"], "2026-01-02T03:04:05.000Z");
+        assert_eq!(body["datacontenttype"], "application/json");
+        assert_eq!(
+            body["data"]["content"],
+            json!({"role": "user", "text": "hello"})
+        );
+        assert_eq!(body["data"]["sequence"], 3);
+    }
+
+    #[test]
+    fn test_binary_mode() {
+        let ctx = ctx();
+        let payload = event(&ctx, MessageKind::Assistant).binary();
+        assert_eq!(header(&payload, "content-type"), Some("application/json"));
+        assert_eq!(header(&payload, "ce-specversion"), Some("1.0"));
+        assert!(header(&payload, "ce-id").is_some_and(is_uuid));
+        assert_eq!(
+            header(&payload, "ce-source"),
+            Some("/aloud-code/my%20project/abc123")
+        );
+        assert_eq!(header(&payload, "ce-type"), Some("dev.aloud-code.response"));
+        assert_eq!(
+            header(&payload, "ce-time"),
+            Some("2026-01-02T03:04:05.000Z")
+        );
+        // ボディはイベントエンベロープそのもの
+        assert_eq!(payload.body["schema_version"], 1);
+        assert_eq!(payload.body["type"], "response");
+        assert!(payload.body.get("specversion").is_none());
+    }
+
+    #[test]
+    fn test_encode_segment() {
+        assert_eq!(encode_segment("my-project_1.0~x"), "my-project_1.0~x");
+        assert_eq!(encode_segment("a b/c"), "a%20b%2Fc");
+        assert_eq!(encode_segment("日本"), "%E6%97%A5%E6%9C%AC");
+    }
+}
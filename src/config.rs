@@ -1,52 +1,655 @@
 use anyhow::Result;
 use serde::Deserialize;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::archive::Retention;
+use crate::layers::EffectiveConfig;
+use crate::redact::Redactor;
+use crate::secrets;
+use crate::signature;
+use crate::templates::Templates;
 
 #[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// メッセージに添えるラベル（チーム名、リポジトリの用途など）
+    #[serde(default)]
+    pub labels: Vec<String>,
+    #[serde(default)]
     pub webhook: WebhookConfig,
+    #[serde(default)]
+    pub redact: RedactConfig,
+    /// 名前付きのペイロードテンプレート
+    #[serde(default)]
+    pub templates: BTreeMap<String, TemplateConfig>,
+    /// [webhook] に加えて送信する名前付きの送信先
+    #[serde(default)]
+    pub destinations: BTreeMap<String, WebhookConfig>,
+    /// すべての送信先に共通のHTTPクライアント設定
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub sessions: SessionsConfig,
 }
 
+/// 操作のないセッションをOFFにするまでのデフォルトの日数
+pub const DEFAULT_SESSION_EXPIRE_DAYS: u64 = 7;
+
+/// 送らないプロンプトに付けるデフォルトの接頭辞
+pub const DEFAULT_PRIVATE_PREFIX: &str = "!private";
+
 #[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SessionsConfig {
+    /// 最後のメッセージからこの日数が過ぎたセッションはOFFにする（0なら期限なし）
+    pub expire_after_days: Option<u64>,
+    /// 配信の開始・終了をSlack形式の送信先に告知する
+    #[serde(default)]
+    pub announce: bool,
+    /// この接頭辞で始まるプロンプトとその応答は送らない（空文字列なら無効）
+    pub private_prefix: Option<String>,
+}
+
+impl SessionsConfig {
+    pub fn max_idle(&self) -> Option<chrono::Duration> {
+        match self
+            .expire_after_days
+            .unwrap_or(DEFAULT_SESSION_EXPIRE_DAYS)
+        {
+            0 => None,
+            days => chrono::Duration::try_days(days.try_into().ok()?),
+        }
+    }
+
+    /// 送らないプロンプトか（`private_prefix` の後に空白が続くか、`private_prefix` だけ）
+    pub fn is_private(&self, prompt: &str) -> bool {
+        let prefix = self
+            .private_prefix
+            .as_deref()
+            .unwrap_or(DEFAULT_PRIVATE_PREFIX);
+        !prefix.is_empty()
+            && prompt
+                .trim_start()
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    /// 明示的なプロキシURL。省略時は HTTPS_PROXY / HTTP_PROXY / NO_PROXY 環境変数に従う
+    pub proxy: Option<String>,
+    /// 追加で信頼するルート証明書（PEM）のパス
+    #[serde(default)]
+    pub ca_certs: Vec<String>,
+}
+
+impl HttpConfig {
+    /// 環境変数参照を解決したプロキシURL
+    pub fn resolve_proxy(&self) -> Result<Option<String>> {
+        match self.proxy.as_deref().filter(|p| !p.is_empty()) {
+            Some(proxy) => secrets::interpolate(proxy).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(proxy) = self.proxy.as_deref().filter(|p| !p.is_empty()) {
+            let result = if secrets::is_literal(proxy) {
+                reqwest::Proxy::all(proxy)
+                    .map(|_| ())
+                    .map_err(|e| anyhow::anyhow!("プロキシURLが不正です ({})", e))
+            } else {
+                secrets::check_interpolation(proxy)
+            };
+            if let Err(e) = result {
+                problems.push(format!("[http] proxy: {}", e));
+            }
+        }
+        for path in &self.ca_certs {
+            if !expand_home(path).is_file() {
+                problems.push(format!("[http] ca_certs: ファイルがありません: {}", path));
+            }
+        }
+        problems
+    }
+}
+
+/// [webhook] セクションの送信先名
+pub const DEFAULT_DESTINATION: &str = "default";
+
+/// 形式や署名に応じて自動で付与するため、headers で指定できないヘッダー
+const RESERVED_HEADERS: &[&str] = &[
+    "content-type",
+    signature::SIGNATURE_HEADER,
+    signature::TIMESTAMP_HEADER,
+];
+
+/// 送信先の種類
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DestinationKind {
+    /// HTTPでPOSTする
+    #[default]
+    Webhook,
+    /// ステートディレクトリにイベントエンベロープをJSONLで追記する
+    Archive,
+    /// セッションごとのMarkdownファイルに会話を追記する
+    Markdown,
+}
+
+impl DestinationKind {
+    /// 設定ファイルでの名前
+    pub fn name(self) -> &'static str {
+        match self {
+            DestinationKind::Webhook => "webhook",
+            DestinationKind::Archive => "archive",
+            DestinationKind::Markdown => "markdown",
+        }
+    }
+}
+
+/// 送信するペイロードの形式
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+    /// Slackのblocks形式（template で変更可能）
+    #[default]
+    Slack,
+    /// バージョン付きの汎用JSONエンベロープ（schema/event.v1.json）
+    Event,
+    /// CloudEvents 1.0（`data` はイベントエンベロープ）
+    #[serde(rename = "cloudevents")]
+    CloudEvents,
+}
+
+/// CloudEventsのHTTPバインディングのモード
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CloudEventsMode {
+    /// イベント全体を `application/cloudevents+json` のボディで送る
+    #[default]
+    Structured,
+    /// 属性を `ce-*` ヘッダーで、`data` をボディで送る
+    Binary,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct WebhookConfig {
+    /// 送信先の種類
+    #[serde(rename = "type", default)]
+    pub kind: DestinationKind,
+    /// 送信先URL。`${env:NAME}` で環境変数を参照できる
     pub url: Option<String>,
+    /// 標準出力に送信先URLを出力するコマンド（例: `pass show slack/aloud`）
+    pub url_command: Option<String>,
+    /// Slackの投稿先チャンネルの上書き（対応しているWebhookのみ）
+    pub channel: Option<String>,
+    /// ペイロードの形式
+    #[serde(default)]
+    pub format: PayloadFormat,
+    /// ペイロードのテンプレート名（組み込み、または [templates.NAME]）
+    pub template: Option<String>,
+    /// format = "cloudevents" の場合のモード（省略時は structured）
+    pub cloudevents_mode: Option<CloudEventsMode>,
+    /// リクエストにHMAC-SHA256署名を付ける共有シークレット。`${env:NAME}` で環境変数を参照できる
+    pub secret: Option<String>,
+    /// すべてのリクエストに付けるヘッダー。値に `${env:NAME}` を使える
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// `Authorization: Bearer` で送るトークン。`${env:NAME}` で環境変数を参照できる
+    pub bearer_token: Option<String>,
+    /// mTLSのクライアント証明書（PEM）のパス
+    pub client_cert: Option<String>,
+    /// client_cert の秘密鍵（PEM）のパス。証明書と同じファイルに含まれる場合は省略できる
+    pub client_key: Option<String>,
+    /// type = "archive" で保存する日数（省略時は無期限）
+    pub retention_days: Option<u64>,
+    /// type = "archive" の合計サイズの上限（MB、省略時は無制限）
+    pub max_size_mb: Option<u64>,
+    /// type = "markdown" の書き込み先ディレクトリ
+    pub directory: Option<String>,
+    /// 配信中にClaudeへ伝える公開先の説明（例: `the #eng Slack channel`）。空文字列なら伝えない
+    pub notice: Option<String>,
+}
+
+impl WebhookConfig {
+    /// 送信先URL（または url_command）が設定されているか。ファイルに書く種類は常に有効
+    pub fn is_configured(&self) -> bool {
+        self.kind != DestinationKind::Webhook
+            || self.url.as_deref().is_some_and(|u| !u.is_empty())
+            || self.url_command.as_deref().is_some_and(|c| !c.is_empty())
+    }
+
+    /// 環境変数参照と url_command を解決した送信先URLを返す
+    pub fn resolve_url(&self) -> Result<String> {
+        if let Some(command) = self.url_command.as_deref().filter(|c| !c.is_empty()) {
+            return secrets::run_command(command);
+        }
+        secrets::interpolate(self.url.as_deref().unwrap_or_default())
+    }
+
+    /// 環境変数参照を解決した署名用シークレットを返す（未設定なら None）
+    pub fn resolve_secret(&self) -> Result<Option<String>> {
+        match self.secret.as_deref().filter(|s| !s.is_empty()) {
+            Some(secret) => secrets::interpolate(secret).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// 環境変数参照を解決した追加ヘッダー（bearer_token は `Authorization` ヘッダーになる）
+    pub fn resolve_headers(&self) -> Result<Vec<(String, String)>> {
+        let mut headers = Vec::new();
+        for (name, value) in &self.headers {
+            let value = secrets::interpolate(value)
+                .map_err(|e| anyhow::anyhow!("headers.{}: {}", name, e))?;
+            headers.push((name.clone(), value));
+        }
+        if let Some(token) = self.bearer_token.as_deref().filter(|t| !t.is_empty()) {
+            let token =
+                secrets::interpolate(token).map_err(|e| anyhow::anyhow!("bearer_token: {}", e))?;
+            headers.push(("Authorization".to_string(), format!("Bearer {}", token)));
+        }
+        Ok(headers)
+    }
+
+    /// mTLSのクライアント証明書と秘密鍵のパス
+    pub fn client_identity(&self) -> Option<(PathBuf, Option<PathBuf>)> {
+        let cert = self.client_cert.as_deref().filter(|c| !c.is_empty())?;
+        Some((
+            expand_home(cert),
+            self.client_key.as_deref().map(expand_home),
+        ))
+    }
+
+    /// URLやシークレットを平文で含んでいるか（環境変数参照や url_command ではない）
+    pub fn has_literal_secret(&self) -> bool {
+        [
+            self.url.as_deref(),
+            self.secret.as_deref(),
+            self.bearer_token.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.headers.values().map(String::as_str))
+        .any(|v| !v.is_empty() && secrets::is_literal(v))
+    }
+
+    /// type = "archive" の保存期間と容量
+    pub fn retention(&self) -> Retention {
+        Retention {
+            max_age_days: self.retention_days,
+            max_bytes: self.max_size_mb.map(|mb| mb * 1024 * 1024),
+        }
+    }
+
+    /// 配信中にClaudeへ伝える公開先の説明。省略時はWebhookのみ送信先名で伝え、ファイルに書く種類は伝えない
+    pub fn audience(&self, name: &str) -> Option<String> {
+        match self.notice.as_deref() {
+            Some("") => None,
+            Some(notice) => Some(notice.to_string()),
+            None if self.kind == DestinationKind::Webhook => {
+                Some(format!("the \"{}\" webhook", name))
+            }
+            None => None,
+        }
+    }
+
+    /// type = "markdown" の書き込み先ディレクトリ（省略時は `<state_dir>/transcripts`）
+    pub fn transcript_dir(&self) -> Result<PathBuf> {
+        match self.directory.as_deref().filter(|d| !d.is_empty()) {
+            Some(dir) => Ok(expand_home(dir)),
+            None => Ok(state_dir()?.join("transcripts")),
+        }
+    }
+
+    /// 指定されている種類専用のキーと、そのキーを使える種類
+    fn kind_specific_keys(&self) -> Vec<(&'static str, DestinationKind)> {
+        use DestinationKind::*;
+        [
+            ("url", self.url.is_some(), Webhook),
+            ("url_command", self.url_command.is_some(), Webhook),
+            ("channel", self.channel.is_some(), Webhook),
+            ("template", self.template.is_some(), Webhook),
+            ("cloudevents_mode", self.cloudevents_mode.is_some(), Webhook),
+            ("secret", self.secret.is_some(), Webhook),
+            ("headers", !self.headers.is_empty(), Webhook),
+            ("bearer_token", self.bearer_token.is_some(), Webhook),
+            ("client_cert", self.client_cert.is_some(), Webhook),
+            ("client_key", self.client_key.is_some(), Webhook),
+            ("retention_days", self.retention_days.is_some(), Archive),
+            ("max_size_mb", self.max_size_mb.is_some(), Archive),
+            ("directory", self.directory.is_some(), Markdown),
+        ]
+        .into_iter()
+        .filter_map(|(key, set, kind)| set.then_some((key, kind)))
+        .collect()
+    }
+
+    fn problems(&self, name: &str, templates: Option<&Templates>) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (key, kind) in self.kind_specific_keys() {
+            if kind != self.kind {
+                problems.push(format!(
+                    "[{}] {} は type = \"{}\" の場合のみ指定できます",
+                    name,
+                    key,
+                    kind.name()
+                ));
+            }
+        }
+        if self.kind != DestinationKind::Webhook {
+            return problems;
+        }
+        let url = self.url.as_deref().filter(|u| !u.is_empty());
+        if url.is_some() && self.url_command.as_deref().is_some_and(|c| !c.is_empty()) {
+            problems.push(format!(
+                "[{}] url と url_command は同時に指定できません",
+                name
+            ));
+        }
+        if let Some(url) = url {
+            // 環境変数参照を含む場合は構文のみ確認し、URLとしての検証は送信時に行う
+            let result = if secrets::is_literal(url) {
+                validate_url(url)
+            } else {
+                secrets::check_interpolation(url)
+            };
+            if let Err(e) = result {
+                problems.push(format!("[{}] url: {}", name, e));
+            }
+        }
+        if let Some(template) = self.template.as_deref() {
+            if self.format != PayloadFormat::Slack {
+                problems.push(format!(
+                    "[{}] template は format = \"slack\" の場合のみ指定できます",
+                    name
+                ));
+            } else if templates.is_some_and(|t| !t.contains(template)) {
+                problems.push(format!(
+                    "[{}] template: {} が見つかりません",
+                    name, template
+                ));
+            }
+        }
+        if let Some(Err(e)) = self.secret.as_deref().map(secrets::check_interpolation) {
+            problems.push(format!("[{}] secret: {}", name, e));
+        }
+        if let Some(Err(e)) = self
+            .bearer_token
+            .as_deref()
+            .map(secrets::check_interpolation)
+        {
+            problems.push(format!("[{}] bearer_token: {}", name, e));
+        } else if let Some(token) = self
+            .bearer_token
+            .as_deref()
+            .filter(|t| secrets::is_literal(t))
+        {
+            if reqwest::header::HeaderValue::from_str(token).is_err() {
+                problems.push(format!(
+                    "[{}] bearer_token: ヘッダーに使えない文字（改行など）が含まれています",
+                    name
+                ));
+            }
+        }
+        for (header, value) in &self.headers {
+            // 値はログに出さない
+            if reqwest::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                problems.push(format!(
+                    "[{}] headers: ヘッダー名が不正です: {}",
+                    name, header
+                ));
+            } else if RESERVED_HEADERS
+                .iter()
+                .any(|h| h.eq_ignore_ascii_case(header))
+            {
+                problems.push(format!(
+                    "[{}] headers: {} は自動で付与されるため指定できません",
+                    name, header
+                ));
+            } else if self.format == PayloadFormat::CloudEvents
+                && self.cloudevents_mode == Some(CloudEventsMode::Binary)
+                && header
+                    .get(..3)
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case("ce-"))
+            {
+                // binaryモードではCloudEventsの属性を ce-* ヘッダーで送る
+                problems.push(format!(
+                    "[{}] headers: {} はCloudEventsのbinaryモードで属性に使われるため指定できません",
+                    name, header
+                ));
+            } else if header.eq_ignore_ascii_case("authorization") && self.bearer_token.is_some() {
+                problems.push(format!(
+                    "[{}] headers.{} と bearer_token は同時に指定できません",
+                    name, header
+                ));
+            }
+            if let Err(e) = secrets::check_interpolation(value) {
+                problems.push(format!("[{}] headers.{}: {}", name, header, e));
+            } else if secrets::is_literal(value)
+                && reqwest::header::HeaderValue::from_str(value).is_err()
+            {
+                // 環境変数などから読む値は送信時に確認する
+                problems.push(format!(
+                    "[{}] headers.{}: 値にヘッダーに使えない文字（改行など）が含まれています",
+                    name, header
+                ));
+            }
+        }
+        if self.client_key.is_some() && self.client_cert.is_none() {
+            problems.push(format!(
+                "[{}] client_key は client_cert と一緒に指定してください",
+                name
+            ));
+        }
+        for (key, path) in [
+            ("client_cert", &self.client_cert),
+            ("client_key", &self.client_key),
+        ] {
+            if let Some(path) = path.as_deref() {
+                if !expand_home(path).is_file() {
+                    problems.push(format!(
+                        "[{}] {}: ファイルがありません: {}",
+                        name, key, path
+                    ));
+                }
+            }
+        }
+        if self.cloudevents_mode.is_some() && self.format != PayloadFormat::CloudEvents {
+            problems.push(format!(
+                "[{}] cloudevents_mode は format = \"cloudevents\" の場合のみ指定できます",
+                name
+            ));
+        }
+        problems
+    }
+}
+
+/// 送信するJSONを組み立てるminijinjaテンプレート
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateConfig {
+    /// `user-prompt` イベント用
+    pub user: String,
+    /// `stop` イベント用
+    pub assistant: String,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RedactConfig {
+    /// 送信前にマスクする正規表現
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// マスク後の文字列（省略時は `[REDACTED]`）
+    pub replacement: Option<String>,
 }
 
 impl Config {
+    /// カレントディレクトリを起点にグローバル・プロジェクト・環境変数の設定を重ねて読み込む
     pub fn load() -> Result<Self> {
-        let config_path = config_file_path()?;
-        if !config_path.exists() {
-            return Ok(Config::default());
+        let cwd = std::env::current_dir().ok();
+        Self::load_for(cwd.as_deref())
+    }
+
+    /// `cwd` から上位ディレクトリへ `.aloud-code.toml` を探して重ねた設定を読み込む
+    pub fn load_for(cwd: Option<&Path>) -> Result<Self> {
+        let effective = EffectiveConfig::load(cwd)?;
+        for warning in &effective.warnings {
+            eprintln!("aloud-code: 警告: {}", warning);
         }
-        let content = std::fs::read_to_string(&config_path)?;
-        let config: Config = toml::from_str(&content)?;
-        Ok(config)
+        Ok(effective.config)
+    }
+
+    /// TOML文字列をパースする。エラー時は「line X, column Y: 理由」形式のメッセージを返す
+    pub fn parse(content: &str) -> Result<Self> {
+        toml::from_str(content).map_err(|e| anyhow::anyhow!(explain_toml_error(content, &e)))
+    }
+
+    /// パース後の値の妥当性（URL構文など）を検証する
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let templates = match Templates::new(&self.templates) {
+            Ok(templates) => Some(templates),
+            Err(e) => {
+                problems.push(e.to_string());
+                None
+            }
+        };
+        if self.destinations.contains_key(DEFAULT_DESTINATION) {
+            problems.push(format!(
+                "[destinations.{}] この名前は [webhook] 用に予約されています",
+                DEFAULT_DESTINATION
+            ));
+        }
+        for (section, webhook) in self.sections() {
+            problems.extend(webhook.problems(&section, templates.as_ref()));
+        }
+        // アーカイブの保存先は1か所なので、複数あると同じファイルに同じイベントを重ねて書いてしまう
+        let archives: Vec<String> = self
+            .sections()
+            .into_iter()
+            .filter(|(_, w)| w.kind == DestinationKind::Archive)
+            .map(|(section, _)| format!("[{}]", section))
+            .collect();
+        if archives.len() > 1 {
+            problems.push(format!(
+                "{}: type = \"archive\" の送信先は1つだけ指定できます（保存先が同じため）",
+                archives.join(", ")
+            ));
+        }
+        problems.extend(self.http.problems());
+        if let Err(e) = Redactor::new(&self.redact) {
+            problems.push(format!("[redact] patterns: {}", e));
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!(problems.join("\n"))
+        }
+    }
+
+    /// URLが設定されている送信先を (名前, 設定) の組で返す。[webhook] の名前は `default`
+    pub fn destinations(&self) -> Vec<(&str, &WebhookConfig)> {
+        std::iter::once((DEFAULT_DESTINATION, &self.webhook))
+            .chain(self.destinations.iter().map(|(n, w)| (n.as_str(), w)))
+            .filter(|(_, w)| w.is_configured())
+            .collect()
+    }
+
+    /// いずれかの送信先がURLやシークレットを平文で含んでいるか
+    pub fn has_literal_secret(&self) -> bool {
+        self.sections().iter().any(|(_, w)| w.has_literal_secret())
     }
-}
 
-pub fn is_active(session_id: &str) -> bool {
-    sessions_dir()
-        .map(|d| d.join(session_id).exists())
-        .unwrap_or(false)
+    /// 設定ファイル上のセクション名と送信先設定の組
+    fn sections(&self) -> Vec<(String, &WebhookConfig)> {
+        std::iter::once(("webhook".to_string(), &self.webhook))
+            .chain(
+                self.destinations
+                    .iter()
+                    .map(|(n, w)| (format!("destinations.{}", n), w)),
+            )
+            .collect()
+    }
 }
 
-pub fn activate(session_id: &str) -> Result<()> {
-    let dir = sessions_dir()?;
-    std::fs::create_dir_all(&dir)?;
-    std::fs::write(dir.join(session_id), "")?;
-    Ok(())
+/// 先頭の `~/` をホームディレクトリに展開する
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
 }
 
-pub fn deactivate(session_id: &str) -> Result<()> {
-    let path = sessions_dir()?.join(session_id);
-    match std::fs::remove_file(&path) {
-        Ok(()) => Ok(()),
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
-        Err(e) => Err(e.into()),
+/// http(s)の絶対URLであることを確認する
+fn validate_url(url: &str) -> Result<()> {
+    let parsed =
+        reqwest::Url::parse(url).map_err(|e| anyhow::anyhow!("URLが不正です ({}): {}", e, url))?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(()),
+        other => anyhow::bail!("http/https以外のスキームは使えません ({}): {}", other, url),
     }
 }
 
-fn config_file_path() -> Result<PathBuf> {
+/// tomlのエラーを行・列と対処のヒント付きのメッセージに変換する
+fn explain_toml_error(content: &str, err: &toml::de::Error) -> String {
+    let location = err
+        .span()
+        .map(|span| {
+            let (line, column) = line_column(content, span.start);
+            format!("line {}, column {}: ", line, column)
+        })
+        .unwrap_or_default();
+    let message = err.message().trim_end();
+    let hint = if message.starts_with("unknown field") {
+        "\n  ヒント: キー名のタイプミスを確認してください（例: [webhooks] ではなく [webhook]）"
+    } else {
+        ""
+    };
+    format!("{}{}{}", location, message, hint)
+}
+
+/// バイトオフセットを1始まりの (行, 列) に変換する
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map(|l| l.chars().count())
+        .unwrap_or(0)
+        + 1;
+    (line, column)
+}
+
+/// `aloud-code config init` が書き出すテンプレート
+pub fn config_template(url: Option<&str>) -> String {
+    let url_line = match url {
+        Some(url) => format!("url = {}", toml::Value::String(url.to_string())),
+        None => "# url = \"https://hooks.slack.com/services/YOUR/WEBHOOK/URL\"".to_string(),
+    };
+    format!(
+        r#"# aloud-code configuration
+# https://github.com/suzuki-toshihir0/aloud-code
+#
+# Check this file with `aloud-code config validate`
+# and send a test message with `aloud-code test`.
+
+[webhook]
+# Slack Incoming Webhook URL, or any endpoint that accepts a JSON POST.
+{}
+"#,
+        url_line
+    )
+}
+
+pub fn config_file_path() -> Result<PathBuf> {
     // テストや特殊環境での上書きをサポート
     if let Ok(path) = std::env::var("ALOUD_CODE_CONFIG_FILE") {
         return Ok(PathBuf::from(path));
@@ -56,32 +659,25 @@ fn config_file_path() -> Result<PathBuf> {
     Ok(config_dir.join("aloud-code").join("config.toml"))
 }
 
-fn sessions_dir() -> Result<PathBuf> {
+/// セッションの状態やアーカイブを置くディレクトリ
+pub fn state_dir() -> Result<PathBuf> {
     // テストや特殊環境での上書きをサポート
     if let Ok(dir) = std::env::var("ALOUD_CODE_STATE_DIR") {
-        return Ok(PathBuf::from(dir).join("sessions"));
+        return Ok(PathBuf::from(dir));
     }
     let state_dir =
         dirs::state_dir().ok_or_else(|| anyhow::anyhow!("ステートディレクトリが見つかりません"))?;
-    Ok(state_dir.join("aloud-code").join("sessions"))
+    Ok(state_dir.join("aloud-code"))
+}
+
+/// セッションごとの状態の保存先
+pub fn sessions_dir() -> Result<PathBuf> {
+    Ok(state_dir()?.join("sessions"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::Mutex;
-
-    // テスト間でのALOUD_CODE_STATE_DIR環境変数競合を防ぐMutex
-    static ENV_MUTEX: Mutex<()> = Mutex::new(());
-
-    /// テスト用に一時ディレクトリをALOUD_CODE_STATE_DIRに設定してクロージャを実行する
-    fn with_temp_state_dir<F: FnOnce()>(f: F) {
-        let _guard = ENV_MUTEX.lock().unwrap();
-        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
-        std::env::set_var("ALOUD_CODE_STATE_DIR", temp_dir.path());
-        f();
-        std::env::remove_var("ALOUD_CODE_STATE_DIR");
-    }
 
     #[test]
     fn test_config_default_when_no_file() {
@@ -105,48 +701,501 @@ url = "https://hooks.slack.com/services/test"
     }
 
     #[test]
-    fn test_config_parse_invalid_toml() {
-        let invalid_toml = "not valid toml {{{{";
-        let result: Result<Config, _> = toml::from_str(invalid_toml);
-        assert!(result.is_err());
+    fn test_destinations_skip_empty_url() {
+        let config: Config = toml::from_str("[webhook]\nurl = \"\"\n").expect("パース失敗");
+        assert!(config.destinations().is_empty());
+
+        let config: Config =
+            toml::from_str("[webhook]\nurl = \"https://example.com/hook\"\n").expect("パース失敗");
+        let names: Vec<&str> = config.destinations().iter().map(|(n, _)| *n).collect();
+        assert_eq!(names, vec!["default"]);
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_section_with_location() {
+        let toml_str = "# comment\n[webhooks]\nurl = \"https://example.com\"\n";
+        let err = Config::parse(toml_str).unwrap_err().to_string();
+        assert!(err.starts_with("line 2, column 2: "), "位置がない: {}", err);
+        assert!(
+            err.contains("unknown field `webhooks`"),
+            "理由がない: {}",
+            err
+        );
+        assert!(err.contains("ヒント"), "ヒントがない: {}", err);
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_key() {
+        let err = Config::parse("[webhook]\nurl = \"https://example.com\"\nurll = \"x\"\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("line 3, column 1: "), "位置がない: {}", err);
     }
 
     #[test]
-    fn test_active_flag_lifecycle() {
-        // セッションIDごとのフラグ作成・確認・削除をテスト
-        with_temp_state_dir(|| {
-            let session_id = "test-session-lifecycle";
-            let _ = deactivate(session_id);
-            assert!(!is_active(session_id));
+    fn test_validate_url_syntax() {
+        let ok =
+            Config::parse("[webhook]\nurl = \"https://hooks.slack.com/services/x\"\n").unwrap();
+        assert!(ok.validate().is_ok());
 
-            activate(session_id).expect("activate失敗");
-            assert!(is_active(session_id));
+        let relative = Config::parse("[webhook]\nurl = \"hooks.slack.com/services/x\"\n").unwrap();
+        assert!(relative.validate().is_err());
 
-            deactivate(session_id).expect("deactivate失敗");
-            assert!(!is_active(session_id));
-        });
+        let ftp = Config::parse("[webhook]\nurl = \"ftp://example.com/x\"\n").unwrap();
+        let err = ftp.validate().unwrap_err().to_string();
+        assert!(err.contains("[webhook] url"), "{}", err);
     }
 
     #[test]
-    fn test_deactivate_idempotent() {
-        // フラグが存在しなくてもdeactivateはエラーにならない
-        with_temp_state_dir(|| {
-            let _ = deactivate("nonexistent-session");
-            let result = deactivate("nonexistent-session");
-            assert!(result.is_ok());
-        });
+    fn test_parse_channel_labels_and_redact() {
+        let toml_str = r##"
+labels = ["backend", "oncall"]
+
+[webhook]
+url = "https://hooks.slack.com/services/test"
+channel = "#dev-stream"
+
+[redact]
+patterns = ["sk-[A-Za-z0-9]+"]
+"##;
+        let config = Config::parse(toml_str).expect("パース失敗");
+        assert_eq!(config.labels, vec!["backend", "oncall"]);
+        assert_eq!(config.webhook.channel.as_deref(), Some("#dev-stream"));
+        assert_eq!(config.redact.patterns, vec!["sk-[A-Za-z0-9]+"]);
+        assert!(config.validate().is_ok());
     }
 
     #[test]
-    fn test_multiple_sessions_concurrent() {
-        // 複数セッションが同時にONにできることを確認
-        with_temp_state_dir(|| {
-            activate("session-a").expect("session-a activate失敗");
-            activate("session-b").expect("session-b activate失敗");
+    fn test_url_command_and_env_reference() {
+        let config = Config::parse("[webhook]\nurl_command = \"echo https://example.com/x\"\n")
+            .expect("パース失敗");
+        assert!(config.validate().is_ok());
+        assert_eq!(config.destinations().len(), 1);
+        assert!(!config.webhook.has_literal_secret());
+        assert_eq!(
+            config.webhook.resolve_url().unwrap(),
+            "https://example.com/x"
+        );
+
+        // 環境変数参照はvalidateの時点では構文のみ確認する
+        let config = Config::parse("[webhook]\nurl = \"${env:ALOUD_CODE_TEST_UNSET_URL}\"\n")
+            .expect("パース失敗");
+        assert!(config.validate().is_ok());
+        assert!(!config.webhook.has_literal_secret());
+        assert!(config.webhook.resolve_url().is_err());
+    }
+
+    #[test]
+    fn test_url_and_url_command_are_exclusive() {
+        let config =
+            Config::parse("[webhook]\nurl = \"https://example.com\"\nurl_command = \"echo x\"\n")
+                .expect("パース失敗");
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("url_command"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_templates() {
+        let toml_str = r#"
+[webhook]
+url = "https://example.com/hook"
+template = "mine"
+
+[templates.mine]
+user = '{"text": {{ prompt | tojson }}}'
+assistant = '{"text": {{ message | tojson }'
+"#;
+        let config = Config::parse(toml_str).expect("パース失敗");
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("[templates.mine] assistant"), "{}", err);
+
+        let config =
+            Config::parse("[webhook]\nurl = \"https://example.com\"\ntemplate = \"nope\"\n")
+                .expect("パース失敗");
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("[webhook] template"), "{}", err);
+
+        let config =
+            Config::parse("[webhook]\nurl = \"https://example.com\"\ntemplate = \"discord\"\n")
+                .expect("パース失敗");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_named_destinations() {
+        let toml_str = r#"
+[webhook]
+url = "https://hooks.slack.com/services/x"
+
+[destinations.ingest]
+url = "https://ingest.example.com/events"
+format = "event"
+
+[destinations.unused]
+"#;
+        let config = Config::parse(toml_str).expect("パース失敗");
+        assert!(config.validate().is_ok());
+        let destinations = config.destinations();
+        let names: Vec<&str> = destinations.iter().map(|(n, _)| *n).collect();
+        assert_eq!(names, vec!["default", "ingest"]);
+        assert_eq!(destinations[0].1.format, PayloadFormat::Slack);
+        assert_eq!(destinations[1].1.format, PayloadFormat::Event);
+    }
+
+    #[test]
+    fn test_cloudevents_format() {
+        let toml_str = r#"
+[destinations.bus]
+url = "https://bus.example.com/events"
+format = "cloudevents"
+cloudevents_mode = "binary"
+"#;
+        let config = Config::parse(toml_str).expect("パース失敗");
+        assert!(config.validate().is_ok());
+        let bus = &config.destinations["bus"];
+        assert_eq!(bus.format, PayloadFormat::CloudEvents);
+        assert_eq!(bus.cloudevents_mode, Some(CloudEventsMode::Binary));
+
+        let config = Config::parse(
+            "[webhook]\nurl = \"https://example.com\"\ncloudevents_mode = \"binary\"\n",
+        )
+        .expect("パース失敗");
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("cloudevents_mode"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_destination_problems() {
+        let toml_str = r#"
+[destinations.default]
+url = "https://example.com"
+
+[destinations.ingest]
+url = "not a url"
+format = "event"
+template = "discord"
+"#;
+        let err = Config::parse(toml_str)
+            .unwrap()
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("[destinations.default]"), "{}", err);
+        assert!(err.contains("[destinations.ingest] url"), "{}", err);
+        assert!(err.contains("[destinations.ingest] template"), "{}", err);
+
+        let err = Config::parse("[webhook]\nformat = \"xml\"\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn test_destination_secret() {
+        let config = Config::parse(
+            "[destinations.ingest]\nurl = \"${env:INGEST_URL}\"\nsecret = \"${env:ALOUD_CODE_TEST_UNSET_SECRET}\"\n",
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert!(!config.has_literal_secret());
+        let ingest = &config.destinations["ingest"];
+        assert!(
+            ingest.resolve_secret().is_err(),
+            "未設定の環境変数が解決された"
+        );
+        assert_eq!(config.webhook.resolve_secret().unwrap(), None);
+
+        let config =
+            Config::parse("[webhook]\nurl = \"${env:URL}\"\nsecret = \"s3cret\"\n").unwrap();
+        assert!(
+            config.has_literal_secret(),
+            "平文のシークレットを検出できない"
+        );
+        assert_eq!(
+            config.webhook.resolve_secret().unwrap().as_deref(),
+            Some("s3cret")
+        );
+
+        let config = Config::parse("[webhook]\nsecret = \"${vault:x}\"\n").unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("[webhook] secret"), "{}", err);
+    }
+
+    #[test]
+    fn test_destination_headers_and_bearer_token() {
+        let toml_str = r#"
+[webhook]
+url = "https://example.com"
+bearer_token = "${env:ALOUD_CODE_TEST_TOKEN}"
+
+[webhook.headers]
+X-Tenant = "acme"
+"#;
+        let config = Config::parse(toml_str).unwrap();
+        assert!(config.validate().is_ok());
+        assert!(config.has_literal_secret());
+        std::env::set_var("ALOUD_CODE_TEST_TOKEN", "t0ken");
+        let headers = config.webhook.resolve_headers().unwrap();
+        std::env::remove_var("ALOUD_CODE_TEST_TOKEN");
+        assert_eq!(
+            headers,
+            vec![
+                ("X-Tenant".to_string(), "acme".to_string()),
+                ("Authorization".to_string(), "Bearer t0ken".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_header_problems() {
+        let toml_str = r#"
+[webhook]
+url = "https://example.com"
+bearer_token = "t0ken"
+
+[webhook.headers]
+"Bad Name" = "x"
+Content-Type = "text/plain"
+Authorization = "Basic c2VjcmV0"
+X-Api-Key = "${env:KEY"
+X-Multi-Line = "a\nb"
+"#;
+        let err = Config::parse(toml_str)
+            .unwrap()
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Bad Name"), "{}", err);
+        assert!(err.contains("Content-Type"), "{}", err);
+        assert!(err.contains("bearer_token は同時に"), "{}", err);
+        assert!(err.contains("headers.X-Api-Key"), "{}", err);
+        assert!(
+            err.contains("headers.X-Multi-Line: 値にヘッダーに使えない文字"),
+            "{}",
+            err
+        );
+        // ヘッダーの値はエラーメッセージに含めない
+        assert!(!err.contains("c2VjcmV0"), "{}", err);
+        assert!(!err.contains("${env:KEY"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_cloudevents_attribute_headers() {
+        let binary = r#"
+[webhook]
+url = "https://example.com"
+format = "cloudevents"
+cloudevents_mode = "binary"
+
+[webhook.headers]
+ce-id = "fixed"
+CE-Source = "x"
+X-Tenant = "acme"
+"#;
+        let err = Config::parse(binary)
+            .unwrap()
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("ce-id はCloudEventsのbinaryモード"), "{}", err);
+        assert!(err.contains("CE-Source"), "{}", err);
+        assert!(!err.contains("X-Tenant"), "{}", err);
+
+        // structuredモードでは属性をボディで送るので、ヘッダーとは衝突しない
+        let structured = binary.replace("cloudevents_mode = \"binary\"\n", "");
+        assert!(Config::parse(&structured).unwrap().validate().is_ok());
+    }
+
+    #[test]
+    fn test_http_and_client_certificate() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let toml_str = format!(
+            "[http]\nproxy = \"http://proxy.example.com:8080\"\nca_certs = [{ca:?}]\n\n\
+             [webhook]\nurl = \"https://relay.example.com\"\nclient_cert = {cert:?}\nclient_key = {key:?}\n",
+            ca = fixtures.join("ca.pem").display().to_string(),
+            cert = fixtures.join("client.pem").display().to_string(),
+            key = fixtures.join("client.key").display().to_string(),
+        );
+        let config = Config::parse(&toml_str).unwrap();
+        assert!(config.validate().is_ok(), "{:?}", config.validate());
+        assert_eq!(
+            config.http.resolve_proxy().unwrap().as_deref(),
+            Some("http://proxy.example.com:8080")
+        );
+        let (cert, key) = config.webhook.client_identity().unwrap();
+        assert_eq!(cert, fixtures.join("client.pem"));
+        assert_eq!(key, Some(fixtures.join("client.key")));
+
+        let toml_str = r#"
+[http]
+proxy = "not a proxy"
+ca_certs = ["/nonexistent/ca.pem"]
+
+[webhook]
+url = "https://relay.example.com"
+client_key = "/nonexistent/client.key"
+"#;
+        let err = Config::parse(toml_str)
+            .unwrap()
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("[http] proxy"), "{}", err);
+        assert!(err.contains("[http] ca_certs"), "{}", err);
+        assert!(
+            err.contains("client_key は client_cert と一緒に"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_expand_home() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_home("~/certs/ca.pem"), home.join("certs/ca.pem"));
+        assert_eq!(expand_home("/etc/ca.pem"), PathBuf::from("/etc/ca.pem"));
+    }
+
+    #[test]
+    fn test_archive_destination() {
+        let config = Config::parse(
+            "[destinations.log]\ntype = \"archive\"\nretention_days = 30\nmax_size_mb = 2\n",
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let err = Config::parse(
+            "[destinations.log]\ntype = \"archive\"\n\n[destinations.backup]\ntype = \"archive\"\n",
+        )
+        .unwrap()
+        .validate()
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains(
+                "[destinations.backup], [destinations.log]: type = \"archive\" の送信先は1つだけ"
+            ),
+            "{}",
+            err
+        );
+        // URLがなくても有効な送信先になる
+        assert_eq!(config.destinations().len(), 1);
+        assert_eq!(
+            config.destinations["log"].retention(),
+            Retention {
+                max_age_days: Some(30),
+                max_bytes: Some(2 * 1024 * 1024),
+            }
+        );
+
+        let toml_str = r#"
+[webhook]
+url = "https://example.com"
+retention_days = 7
+
+[destinations.log]
+type = "archive"
+url = "https://example.com"
+template = "discord"
+"#;
+        let err = Config::parse(toml_str)
+            .unwrap()
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("[webhook] retention_days"), "{}", err);
+        assert!(err.contains("[destinations.log] url"), "{}", err);
+        assert!(err.contains("[destinations.log] template"), "{}", err);
+    }
 
-            assert!(is_active("session-a"), "session-aがアクティブでない");
-            assert!(is_active("session-b"), "session-bがアクティブでない");
-            assert!(!is_active("session-c"), "session-cがアクティブになっている");
-        });
+    #[test]
+    fn test_markdown_destination() {
+        let config = Config::parse(
+            "[destinations.notes]\ntype = \"markdown\"\ndirectory = \"~/notes/aloud\"\n",
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.destinations().len(), 1);
+        assert_eq!(
+            config.destinations["notes"].transcript_dir().unwrap(),
+            dirs::home_dir().unwrap().join("notes/aloud")
+        );
+
+        let config = Config::parse(
+            "[destinations.notes]\ntype = \"markdown\"\nretention_days = 1\n\n\
+             [destinations.log]\ntype = \"archive\"\ndirectory = \"/tmp\"\n",
+        )
+        .unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(
+            err.contains("[destinations.notes] retention_days は type = \"archive\""),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("[destinations.log] directory は type = \"markdown\""),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_invalid_redact_pattern() {
+        let config = Config::parse("[redact]\npatterns = [\"(unclosed\"]\n").unwrap();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("[redact] patterns"), "{}", err);
+    }
+
+    #[test]
+    fn test_config_template_is_valid() {
+        let config = Config::parse(&config_template(None)).expect("テンプレートのパース失敗");
+        assert!(config.webhook.url.is_none());
+
+        let config = Config::parse(&config_template(Some("https://example.com/hook")))
+            .expect("テンプレートのパース失敗");
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.webhook.url.as_deref(),
+            Some("https://example.com/hook")
+        );
+    }
+
+    #[test]
+    fn test_session_expiry() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.sessions.max_idle(), Some(chrono::Duration::days(7)));
+        let config = Config::parse("[sessions]\nexpire_after_days = 30\n").unwrap();
+        assert_eq!(config.sessions.max_idle(), Some(chrono::Duration::days(30)));
+        let config = Config::parse("[sessions]\nexpire_after_days = 0\n").unwrap();
+        assert_eq!(config.sessions.max_idle(), None, "0なら期限なし");
+        assert!(Config::parse("[sessions]\nttl = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_private_prefix() {
+        let config = Config::parse("").unwrap();
+        assert!(config.sessions.is_private("!private here is the key"));
+        assert!(config.sessions.is_private("  !private"));
+        assert!(!config.sessions.is_private("not !private"));
+        assert!(config.sessions.is_private("!private\nmultiline secret"));
+        assert!(!config.sessions.is_private("!privately speaking"));
+        let config = Config::parse("[sessions]\nprivate_prefix = \"%%\"\n").unwrap();
+        assert!(config.sessions.is_private("%% secret"));
+        assert!(!config.sessions.is_private("!private secret"));
+        assert!(
+            !config.sessions.is_private("%%d is a printf format"),
+            "接頭辞の直後に空白がなければ通常のプロンプト"
+        );
+        let config = Config::parse("[sessions]\nprivate_prefix = \"\"\n").unwrap();
+        assert!(
+            !config.sessions.is_private("!private secret"),
+            "空文字列なら無効"
+        );
+    }
+
+    #[test]
+    fn test_config_parse_invalid_toml() {
+        let invalid_toml = "not valid toml {{{{";
+        let result: Result<Config, _> = toml::from_str(invalid_toml);
+        assert!(result.is_err());
     }
 }
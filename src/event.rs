@@ -0,0 +1,165 @@
+use chrono::SecondsFormat;
+use serde::{Deserialize, Serialize};
+
+use crate::formatter::{Message, MessageKind};
+
+/// エンベロープのスキーマバージョン（互換性のない変更をしたら上げ、schema/ に新しいファイルを追加する）
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// `format = "event"` の送信先に送るJSON（スキーマ: schema/event.v1.json）
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct EventEnvelope {
+    pub schema_version: u32,
+    /// `prompt` または `response`
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub session_id: String,
+    pub project: String,
+    pub cwd: String,
+    pub model: Option<String>,
+    pub labels: Vec<String>,
+    /// セッションのタイトル（付けた場合のみ）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// セッションのタグ（付けた場合のみ）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// RFC 3339形式（UTC、ミリ秒まで）
+    pub timestamp: String,
+    /// セッション内の通し番号（1始まり）
+    pub sequence: u64,
+    pub content: EventContent,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct EventContent {
+    /// `user` または `assistant`
+    pub role: String,
+    /// マスク済みの本文（切り詰めない）
+    pub text: String,
+}
+
+impl EventEnvelope {
+    pub fn new(message: &Message) -> Self {
+        let ctx = message.ctx;
+        EventEnvelope {
+            schema_version: SCHEMA_VERSION,
+            event_type: message.kind.event_type().to_string(),
+            session_id: ctx.session_id.clone(),
+            project: ctx.project_name().to_string(),
+            cwd: ctx.cwd.clone(),
+            model: ctx.model.clone(),
+            labels: ctx.labels.clone(),
+            title: ctx.title.clone(),
+            tags: ctx.tags.clone(),
+            timestamp: message
+                .timestamp
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            sequence: message.sequence,
+            content: EventContent {
+                role: match message.kind {
+                    MessageKind::User => "user",
+                    MessageKind::Assistant => "assistant",
+                }
+                .to_string(),
+                text: message.text.to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatter::SessionContext;
+    use chrono::{TimeZone, Utc};
+    use serde_json::Value;
+    use std::collections::BTreeSet;
+
+    fn schema() -> Value {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("schema/event.v1.json");
+        serde_json::from_str(&std::fs::read_to_string(path).expect("スキーマファイルがない"))
+            .expect("スキーマのパース失敗")
+    }
+
+    fn golden(file: &str) -> Value {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(file);
+        serde_json::from_str(&std::fs::read_to_string(path).expect("goldenファイルがない"))
+            .expect("goldenファイルのパース失敗")
+    }
+
+    fn envelope(kind: MessageKind, text: &str) -> Value {
+        envelope_with(kind, text, None, &[])
+    }
+
+    fn envelope_with(kind: MessageKind, text: &str, title: Option<&str>, tags: &[&str]) -> Value {
+        let ctx = SessionContext {
+            session_id: "abcdef12-3456-7890-abcd-ef1234567890".to_string(),
+            cwd: "/home/user/my-project".to_string(),
+            model: Some("claude-sonnet-4-6".to_string()),
+            labels: vec!["backend".to_string()],
+            title: title.map(|t| t.to_string()),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        };
+        let message = Message {
+            kind,
+            text,
+            ctx: &ctx,
+            timestamp: Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap(),
+            sequence: 7,
+            git: None,
+        };
+        serde_json::to_value(EventEnvelope::new(&message)).unwrap()
+    }
+
+    fn keys(value: &Value) -> BTreeSet<String> {
+        value.as_object().unwrap().keys().cloned().collect()
+    }
+
+    #[test]
+    fn test_envelope_matches_golden_files() {
+        assert_eq!(
+            envelope(MessageKind::User, "Fix the flaky test"),
+            golden("event_prompt.json")
+        );
+        assert_eq!(
+            envelope(MessageKind::Assistant, "Done."),
+            golden("event_response.json")
+        );
+    }
+
+    #[test]
+    fn test_schema_pins_envelope_fields() {
+        // スキーマとエンベロープのフィールドが食い違ったら失敗させる
+        let schema = schema();
+        let value = envelope(MessageKind::User, "hi");
+        // タイトルとタグは付けたときだけ含める
+        let full = envelope_with(MessageKind::User, "hi", Some("Fix"), &["backend"]);
+        assert_eq!(full["title"], "Fix");
+        assert_eq!(full["tags"], serde_json::json!(["backend"]));
+
+        let properties: BTreeSet<String> = keys(&schema["properties"]);
+        let required: BTreeSet<String> = schema["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(properties, keys(&full));
+        assert_eq!(required, keys(&value));
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(
+            schema["properties"]["schema_version"]["const"],
+            SCHEMA_VERSION
+        );
+
+        let content = &schema["properties"]["content"];
+        assert_eq!(keys(&content["properties"]), keys(&value["content"]));
+        assert_eq!(
+            schema["properties"]["type"]["enum"],
+            serde_json::json!(["prompt", "response"])
+        );
+    }
+}
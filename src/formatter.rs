@@ -1,11 +1,63 @@
+use chrono::{DateTime, Utc};
 use serde_json::{json, Value};
 
+use crate::git::GitInfo;
+
 const MAX_BLOCK_TEXT_LEN: usize = 3000;
+/// Slackのusernameの最大文字数
+const MAX_USERNAME_LEN: usize = 80;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct SessionContext {
     pub session_id: String,
     pub cwd: String,
+    pub model: Option<String>,
+    pub labels: Vec<String>,
+    /// `/aloud-code:on "タイトル"` で付けたセッションのタイトル
+    pub title: Option<String>,
+    /// `/aloud-code:on #タグ` で付けたタグ（`#` は含まない）
+    pub tags: Vec<String>,
+}
+
+/// 送信するメッセージの種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageKind {
+    /// ユーザーのプロンプト（`user-prompt` イベント）
+    User,
+    /// Claudeの最終応答（`stop` イベント）
+    Assistant,
+}
+
+impl MessageKind {
+    /// 対応するhookイベント名
+    pub fn event_name(self) -> &'static str {
+        match self {
+            MessageKind::User => "user-prompt",
+            MessageKind::Assistant => "stop",
+        }
+    }
+
+    /// イベントエンベロープの `type`
+    pub fn event_type(self) -> &'static str {
+        match self {
+            MessageKind::User => "prompt",
+            MessageKind::Assistant => "response",
+        }
+    }
+}
+
+/// 送信する1件のメッセージと送信時点の情報
+#[derive(Debug)]
+pub struct Message<'a> {
+    pub kind: MessageKind,
+    /// マスク済みの本文
+    pub text: &'a str,
+    pub ctx: &'a SessionContext,
+    pub timestamp: DateTime<Utc>,
+    /// セッション内の通し番号（1始まり）
+    pub sequence: u64,
+    /// テンプレートを使う送信先がある場合のみ取得する
+    pub git: Option<GitInfo>,
 }
 
 impl SessionContext {
@@ -23,18 +75,27 @@ impl SessionContext {
             .unwrap_or(&self.cwd)
     }
 
-    /// Slackのusernameフィールド用文字列（"project [session_id短縮]"）
+    /// Slackのusernameフィールド用文字列（"project [session_id短縮]"、タイトルがあれば先頭に付ける）
     pub fn username(&self) -> String {
-        format!("{} [{}]", self.project_name(), self.short_id())
+        let name = format!("{} [{}]", self.project_name(), self.short_id());
+        match &self.title {
+            Some(title) => {
+                // 長いタイトルを切り詰めてもプロジェクト名とIDは残す
+                let max_title = MAX_USERNAME_LEN.saturating_sub(name.len() + 3).max(4);
+                format!("{} · {}", truncate_chars(title, max_title), name)
+            }
+            None => name,
+        }
     }
 }
 
-fn truncate(text: &str, max_len: usize) -> String {
+pub(crate) fn truncate(text: &str, max_len: usize) -> String {
     if text.len() <= max_len {
         text.to_string()
     } else {
         // UTF-8の文字境界を考慮してトランケート
-        let mut end = max_len - 3; // "..."の分
+        // "..."の分。テンプレートの `truncate(n)` で小さい値を指定されても下回らない
+        let mut end = max_len.saturating_sub(3);
         while !text.is_char_boundary(end) {
             end -= 1;
         }
@@ -42,36 +103,82 @@ fn truncate(text: &str, max_len: usize) -> String {
     }
 }
 
-pub fn format_user_message(prompt: &str, ctx: &SessionContext) -> Value {
-    let text = truncate(prompt, MAX_BLOCK_TEXT_LEN);
+/// 文字数で切り詰める（usernameの上限はバイト数ではなく文字数）
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        text.to_string()
+    } else {
+        let head: String = text.chars().take(max_chars - 1).collect();
+        format!("{}…", head)
+    }
+}
+
+fn format_message(header: &str, body: &str, ctx: &SessionContext) -> Value {
+    let text = truncate(body, MAX_BLOCK_TEXT_LEN);
+    let mut blocks = vec![json!({
+        "type": "section",
+        "text": {
+            "type": "mrkdwn",
+            "text": format!("{}\n{}", header, text)
+        }
+    })];
+    let mut context = Vec::new();
+    if !ctx.labels.is_empty() {
+        context.push(json!({
+            "type": "mrkdwn",
+            "text": format!(":label: {}", ctx.labels.join(" · "))
+        }));
+    }
+    if !ctx.tags.is_empty() {
+        let tags: Vec<String> = ctx.tags.iter().map(|t| format!("#{}", t)).collect();
+        context.push(json!({
+            "type": "plain_text",
+            "text": tags.join(" ")
+        }));
+    }
+    if !context.is_empty() {
+        blocks.push(json!({
+            "type": "context",
+            "elements": context
+        }));
+    }
     json!({
         "username": ctx.username(),
-        "blocks": [
-            {
-                "type": "section",
-                "text": {
-                    "type": "mrkdwn",
-                    "text": format!(":bust_in_silhouette: *User*\n{}", text)
-                }
-            }
-        ]
+        "blocks": blocks
     })
 }
 
+pub fn format_user_message(prompt: &str, ctx: &SessionContext) -> Value {
+    format_message(":bust_in_silhouette: *User*", prompt, ctx)
+}
+
 pub fn format_assistant_message(message: &str, ctx: &SessionContext) -> Value {
-    let text = truncate(message, MAX_BLOCK_TEXT_LEN);
-    json!({
-        "username": ctx.username(),
-        "blocks": [
-            {
-                "type": "section",
-                "text": {
-                    "type": "mrkdwn",
-                    "text": format!(":robot_face: *Claude*\n{}", text)
-                }
-            }
-        ]
-    })
+    format_message(":robot_face: *Claude*", message, ctx)
+}
+
+/// `/aloud-code:note` で投稿する人間のメモ
+pub fn format_note(text: &str, who: Option<&str>, ctx: &SessionContext) -> Value {
+    let header = match who {
+        Some(who) => format!(":memo: *Note from {}*", who),
+        None => ":memo: *Note*".to_string(),
+    };
+    format_message(&header, text, ctx)
+}
+
+/// 種類に応じたデフォルト（Slack形式）のペイロードを返す
+pub fn format_kind(kind: MessageKind, text: &str, ctx: &SessionContext) -> Value {
+    match kind {
+        MessageKind::User => format_user_message(text, ctx),
+        MessageKind::Assistant => format_assistant_message(text, ctx),
+    }
+}
+
+/// 送信先ごとのチャンネル指定をペイロードに反映する
+pub fn with_channel(mut payload: Value, channel: Option<&str>) -> Value {
+    if let Some(channel) = channel.filter(|c| !c.is_empty()) {
+        payload["channel"] = json!(channel);
+    }
+    payload
 }
 
 #[cfg(test)]
@@ -82,6 +189,7 @@ mod tests {
         SessionContext {
             session_id: "abcdef1234567890".to_string(),
             cwd: "/home/user/my-project".to_string(),
+            ..Default::default()
         }
     }
 
@@ -128,6 +236,59 @@ mod tests {
         assert!(text.as_str().unwrap().contains("Sure, I can help!"));
     }
 
+    #[test]
+    fn test_labels_context_block() {
+        let ctx = SessionContext {
+            labels: vec!["backend".to_string(), "oncall".to_string()],
+            ..test_ctx()
+        };
+        let payload = format_user_message("hi", &ctx);
+        let blocks = payload["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1]["type"], "context");
+        assert_eq!(blocks[1]["elements"][0]["text"], ":label: backend · oncall");
+    }
+
+    #[test]
+    fn test_title_and_tags() {
+        let ctx = SessionContext {
+            title: Some("Fix flaky auth tests".to_string()),
+            tags: vec!["backend".to_string(), "auth".to_string()],
+            ..test_ctx()
+        };
+        assert_eq!(
+            ctx.username(),
+            "Fix flaky auth tests · my-project [abcdef12]"
+        );
+        let payload = format_user_message("hi", &ctx);
+        let context = &payload["blocks"][1];
+        assert_eq!(context["type"], "context");
+        assert_eq!(context["elements"][0]["text"], "#backend #auth");
+
+        // 長いタイトルは切り詰めてプロジェクト名とIDを残す
+        let ctx = SessionContext {
+            title: Some("あ".repeat(100)),
+            ..test_ctx()
+        };
+        let username = ctx.username();
+        assert_eq!(username.chars().count(), MAX_USERNAME_LEN);
+        assert!(
+            username.ends_with("… · my-project [abcdef12]"),
+            "{}",
+            username
+        );
+    }
+
+    #[test]
+    fn test_with_channel() {
+        let ctx = test_ctx();
+        let payload = with_channel(format_user_message("hi", &ctx), Some("#dev"));
+        assert_eq!(payload["channel"], "#dev");
+
+        let payload = with_channel(format_user_message("hi", &ctx), None);
+        assert!(payload.get("channel").is_none());
+    }
+
     #[test]
     fn test_truncate_long_message() {
         let ctx = test_ctx();
@@ -138,6 +299,15 @@ mod tests {
         assert!(text.len() <= MAX_BLOCK_TEXT_LEN + 50);
     }
 
+    #[test]
+    fn test_truncate_short_limits() {
+        assert_eq!(truncate("hello", 5), "hello");
+        assert_eq!(truncate("hello", 4), "h...");
+        assert_eq!(truncate("hello", 2), "...");
+        assert_eq!(truncate("hello", 0), "...");
+        assert_eq!(truncate("あいう", 5), "...");
+    }
+
     #[test]
     fn test_slack_emoji_codes_not_unicode() {
         let ctx = test_ctx();
@@ -0,0 +1,101 @@
+use serde::Serialize;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// 作業ディレクトリのgitリポジトリ情報
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GitInfo {
+    /// 現在のブランチ名（detached HEAD の場合は `HEAD`）
+    pub branch: Option<String>,
+    /// HEADの短縮コミットハッシュ
+    pub commit: Option<String>,
+    /// リポジトリのルートディレクトリ名
+    pub repository: Option<String>,
+}
+
+impl GitInfo {
+    /// `cwd` がgitリポジトリ内ならその情報を返す
+    pub fn detect(cwd: &str) -> Option<Self> {
+        if cwd.is_empty() || !Path::new(cwd).is_dir() {
+            return None;
+        }
+        let root = git(cwd, &["rev-parse", "--show-toplevel"])?;
+        Some(GitInfo {
+            branch: git(cwd, &["rev-parse", "--abbrev-ref", "HEAD"]),
+            commit: git(cwd, &["rev-parse", "--short", "HEAD"]),
+            repository: Path::new(&root)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned()),
+        })
+    }
+}
+
+/// `cwd` で有効なgitの `user.name`
+pub fn user_name(cwd: &str) -> Option<String> {
+    if cwd.is_empty() || !Path::new(cwd).is_dir() {
+        return None;
+    }
+    git(cwd, &["config", "user.name"])
+}
+
+fn git(cwd: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(cwd)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_outside_repository() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        assert_eq!(GitInfo::detect(&temp_dir.path().to_string_lossy()), None);
+        assert_eq!(GitInfo::detect(""), None);
+        assert_eq!(GitInfo::detect("/nonexistent/aloud-code"), None);
+    }
+
+    #[test]
+    fn test_detect_repository() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let repo = temp_dir.path().join("my-repo");
+        std::fs::create_dir(&repo).unwrap();
+        let cwd = repo.to_string_lossy().into_owned();
+        let run = |args: &[&str]| {
+            Command::new("git")
+                .arg("-C")
+                .arg(&cwd)
+                .args(args)
+                .output()
+                .expect("git実行失敗")
+        };
+        run(&["init", "-q", "-b", "feature-x"]);
+        run(&[
+            "-c",
+            "user.name=test",
+            "-c",
+            "user.email=test@example.com",
+            "commit",
+            "-q",
+            "--allow-empty",
+            "-m",
+            "init",
+        ]);
+
+        let info = GitInfo::detect(&cwd).expect("リポジトリとして検出されない");
+        assert_eq!(info.branch.as_deref(), Some("feature-x"));
+        assert_eq!(info.repository.as_deref(), Some("my-repo"));
+        assert!(info.commit.is_some());
+    }
+}
@@ -1,10 +1,24 @@
 use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
 use serde::Deserialize;
-use std::io::Read;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
-use crate::config::{self, Config};
-use crate::formatter::{self, SessionContext};
-use crate::webhook::WebhookSender;
+use crate::announce::{self, plural, Announcement, AnnouncementKind};
+use crate::archive;
+use crate::cloudevents::CloudEvent;
+use crate::config::{
+    self, CloudEventsMode, Config, DestinationKind, HttpConfig, PayloadFormat, WebhookConfig,
+};
+use crate::event::EventEnvelope;
+use crate::formatter::{self, Message, MessageKind, SessionContext};
+use crate::git::GitInfo;
+use crate::redact::Redactor;
+use crate::session::{self, SessionState, SessionStore};
+use crate::templates::{TemplateContext, Templates};
+use crate::transcript;
+use crate::webhook::{ClientOptions, Payload, WebhookSender};
 
 #[derive(Debug, Deserialize, Default)]
 #[allow(dead_code)]
@@ -35,73 +49,758 @@ impl HookInput {
         SessionContext {
             session_id: self.session_id.clone().unwrap_or_default(),
             cwd: self.cwd.clone().unwrap_or_default(),
+            model: self.model.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Claudeに渡さずhookで処理する `/aloud-code:*` コマンドかどうかを判定
+pub fn is_command(prompt: &str) -> bool {
+    Command::parse(prompt).is_some()
+}
+
+/// セッション中に使うコマンドの内容
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// `/aloud-code:on ["タイトル"] [#タグ...] [送信先...] [時間]`（`/aloud-code:preview` も同じ）
+    On {
+        preview: bool,
+        title: Option<String>,
+        tags: Vec<String>,
+        /// このセッションで使う送信先の名前（空ならすべて）
+        destinations: Vec<String>,
+        duration: Option<chrono::Duration>,
+    },
+    Off,
+    /// `/aloud-code:status`
+    Status,
+    /// `/aloud-code:skip`: 次のプロンプトとその応答を送らない
+    Skip,
+    /// `/aloud-code:note <テキスト>`
+    Note(String),
+    /// 引数の誤ったコマンド。普通のプロンプトとして扱わず、使い方を表示してブロックする
+    Invalid {
+        usage: &'static str,
+    },
+}
+
+impl Command {
+    pub fn parse(prompt: &str) -> Option<Self> {
+        let prompt = prompt.trim();
+        let (command, rest) = prompt
+            .split_once(char::is_whitespace)
+            .unwrap_or((prompt, ""));
+        Self::parse_args(command, rest)
+            .or_else(|| usage(command).map(|usage| Command::Invalid { usage }))
+    }
+
+    fn parse_args(command: &str, rest: &str) -> Option<Self> {
+        // メモは引用符も含めてそのまま送る
+        if command == "/aloud-code:note" {
+            let text = rest.trim();
+            return (!text.is_empty()).then(|| Command::Note(text.to_string()));
         }
+        let args = split_args(rest)?;
+        match command {
+            "/aloud-code:off" if args.is_empty() => Some(Command::Off),
+            "/aloud-code:status" if args.is_empty() => Some(Command::Status),
+            "/aloud-code:skip" if args.is_empty() => Some(Command::Skip),
+            "/aloud-code:on" | "/aloud-code:preview" => {
+                let mut title = None;
+                let mut tags = Vec::new();
+                let mut destinations = Vec::new();
+                let mut duration = None;
+                for arg in args {
+                    match arg {
+                        // タイトルと時間は1つだけ
+                        Arg::Quoted(_) if title.is_some() => return None,
+                        Arg::Quoted(text) => title = Some(text).filter(|t| !t.is_empty()),
+                        Arg::Word(word) => {
+                            if let Some(tag) = word.strip_prefix('#').filter(|t| !t.is_empty()) {
+                                tags.push(tag.to_string());
+                            } else if let Some(d) = parse_duration(&word) {
+                                if duration.replace(d).is_some() {
+                                    return None;
+                                }
+                            } else {
+                                destinations.push(word);
+                            }
+                        }
+                    }
+                }
+                Some(Command::On {
+                    preview: command == "/aloud-code:preview",
+                    title,
+                    tags,
+                    destinations,
+                    duration,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// 引数が誤っていたときに表示するコマンドの使い方（対象外のコマンドは `None`）
+fn usage(command: &str) -> Option<&'static str> {
+    match command {
+        "/aloud-code:on" => Some(
+            r#"/aloud-code:on ["title"] [#tag ...] [destination ...] [duration, e.g. 90m or 1h30m]"#,
+        ),
+        "/aloud-code:preview" => Some(
+            r#"/aloud-code:preview ["title"] [#tag ...] [destination ...] [duration, e.g. 90m or 1h30m]"#,
+        ),
+        "/aloud-code:off" => Some("/aloud-code:off (takes no arguments)"),
+        "/aloud-code:status" => Some("/aloud-code:status (takes no arguments)"),
+        "/aloud-code:skip" => Some("/aloud-code:skip (takes no arguments)"),
+        "/aloud-code:note" => Some("/aloud-code:note <text>"),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Arg {
+    /// `"..."` で囲んだ引数（引用符は含まない）
+    Quoted(String),
+    Word(String),
+}
+
+/// 空白区切りの引数に分ける。閉じていない引用符があれば `None`
+fn split_args(text: &str) -> Option<Vec<Arg>> {
+    let mut args = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            args.push(Arg::Quoted(quoted[..end].trim().to_string()));
+            rest = &quoted[end + 1..];
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            args.push(Arg::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Some(args)
+}
+
+/// `90m` / `2h` / `1h30m` 形式の時間
+fn parse_duration(text: &str) -> Option<chrono::Duration> {
+    let mut total = chrono::Duration::zero();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let value: i64 = rest[..digits].parse().ok()?;
+        let unit = match rest[digits..].chars().next()? {
+            'h' => chrono::Duration::try_hours(value)?,
+            'm' => chrono::Duration::try_minutes(value)?,
+            _ => return None,
+        };
+        total = total.checked_add(&unit)?;
+        rest = &rest[digits + 1..];
     }
+    (total > chrono::Duration::zero()).then_some(total)
 }
 
-/// `/aloud-code:on` / `/aloud-code:off` コマンドかどうかを判定
-fn is_toggle_command(prompt: &str) -> bool {
-    matches!(prompt.trim(), "/aloud-code:on" | "/aloud-code:off")
+/// 送信する代わりにペイロードを書き出す先
+#[derive(Debug, PartialEq)]
+pub(crate) enum DryRun {
+    Stderr,
+    File(PathBuf),
 }
 
-/// UserPromptSubmit hook (同期): トグルコマンドを処理
+impl DryRun {
+    /// `ALOUD_CODE_DRY_RUN` の値を解釈する（`1` / `true` / `stderr` は標準エラー出力、それ以外はファイルパス）
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "" | "0" | "false" => None,
+            "1" | "true" | "stderr" => Some(DryRun::Stderr),
+            path => Some(DryRun::File(config::expand_home(path))),
+        }
+    }
+
+    /// 環境変数が優先で、なければセッションがプレビューモードのときにその書き出し先を返す
+    pub(crate) fn for_session(session_id: &str, state: &SessionState) -> Result<Option<Self>> {
+        if let Some(dry_run) = std::env::var("ALOUD_CODE_DRY_RUN")
+            .ok()
+            .and_then(|v| Self::parse(&v))
+        {
+            return Ok(Some(dry_run));
+        }
+        if state.preview {
+            return Ok(Some(DryRun::File(session::preview_file(session_id)?)));
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn write(&self, text: &str) -> Result<()> {
+        match self {
+            DryRun::Stderr => eprint!("{}", text),
+            DryRun::File(path) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                // 並行して動くhookプロセスの出力が混ざらないようにする
+                file.lock()?;
+                file.write_all(text.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// UserPromptSubmit hook (同期): `/aloud-code:*` コマンドを処理する。コマンドはClaudeに渡さずブロックする
 pub async fn handle_toggle() -> Result<()> {
     let input = HookInput::from_stdin()?;
     let prompt = input.prompt.as_deref().unwrap_or("");
     let session_id = input.session_id.as_deref().unwrap_or("");
 
-    match prompt.trim() {
-        "/aloud-code:on" => {
-            config::activate(session_id)?;
+    let store = SessionStore::open()?;
+    let config = Config::load_for(input.cwd.as_deref().map(Path::new));
+    let now = Utc::now();
+    match Command::parse(prompt) {
+        Some(Command::On {
+            preview,
+            title,
+            tags,
+            destinations,
+            duration,
+        }) => {
+            if !destinations.is_empty() {
+                let checked = match &config {
+                    Ok(config) => check_destinations(&destinations, config),
+                    Err(e) => Err(anyhow::anyhow!("{:#}", e)),
+                };
+                if let Err(e) = checked {
+                    // ONにせず、理由をClaude Codeの画面に表示する
+                    block(&format!("aloud-code: streaming not started: {}", e));
+                    return Ok(());
+                }
+            }
+            let state = SessionState {
+                preview,
+                title,
+                tags,
+                destinations: (!destinations.is_empty()).then_some(destinations),
+                expires_at: duration.map(|d| now + d),
+                ..Default::default()
+            };
+            store.activate(session_id, state.clone())?;
+            block(&started_notice(session_id, &state)?);
+            if let Ok(config) = &config {
+                prune_sessions(&store, config);
+                announce(
+                    config,
+                    Announcement::new(AnnouncementKind::Started, &input, state),
+                );
+            }
         }
-        "/aloud-code:off" => {
-            config::deactivate(session_id)?;
+        Some(Command::Invalid { usage }) => {
+            block(&format!("aloud-code: usage: {}", usage));
+        }
+        Some(Command::Off) => {
+            let state = store.load(session_id)?;
+            store.deactivate(session_id)?;
+            block(match state {
+                Some(_) => "aloud-code: streaming off",
+                None => "aloud-code: streaming was not on",
+            });
+            if let (Ok(config), Some(state)) = (&config, state) {
+                announce(
+                    config,
+                    Announcement::new(AnnouncementKind::Stopped, &input, state),
+                );
+            }
+        }
+        Some(Command::Status) => {
+            let state = store.load(session_id)?;
+            block(&status(session_id, state.as_ref(), config.as_ref().ok())?);
+        }
+        Some(Command::Skip) => {
+            let skipping = store.update(session_id, |state| state.skip_next = true)?;
+            block(match skipping {
+                Some(()) => "aloud-code: the next prompt and its response won't be posted",
+                None => "aloud-code: streaming is off, nothing to skip",
+            });
+        }
+        Some(Command::Note(text)) => match (store.load(session_id)?, &config) {
+            (None, _) => block("aloud-code: streaming is off, note not posted"),
+            (Some(_), Err(e)) => block(&format!("aloud-code: note not posted: {:#}", e)),
+            // メモはSlack形式でテンプレートを使わないWebhookにしか送れない
+            (Some(state), Ok(config)) if announce::targets(config, &state).is_empty() => block(
+                "aloud-code: note not posted: no destination accepts notes \
+                 (notes go to Slack-format webhooks without a template)",
+            ),
+            (Some(state), Ok(_)) => {
+                let preview = state.preview;
+                let note =
+                    Announcement::new(AnnouncementKind::Note, &input, state).with_text(&text);
+                match note.spawn() {
+                    Ok(()) if preview => block("aloud-code: note written to the preview"),
+                    Ok(()) => block("aloud-code: note posted"),
+                    Err(e) => block(&format!("aloud-code: note not posted: {}", e)),
+                }
+            }
+        },
+        None => {
+            // 指定された時間が過ぎた後の最初のプロンプトで、配信が終わったことを知らせる
+            if let Some(state) = store.end_if_time_is_up(session_id, now)? {
+                if let Ok(config) = &config {
+                    announce(
+                        config,
+                        Announcement::new(AnnouncementKind::Stopped, &input, state)
+                            .with_reason("time limit reached"),
+                    );
+                }
+                let notice = serde_json::json!({
+                    "systemMessage": "aloud-code: streaming ended (time limit reached)"
+                });
+                println!("{}", notice);
+            } else if let (Some(state), Ok(config)) = (store.load(session_id)?, &config) {
+                // 送らないターンかはここで決めてターンごとに記録する（非同期のhookはこの記録を見る）
+                let private =
+                    store.start_turn(session_id, prompt, config.sessions.is_private(prompt))?
+                        == Some(true);
+                // 配信中であることをClaudeに伝え、秘密情報を出力しないよう促す
+                let context = match DryRun::for_session(session_id, &state)? {
+                    Some(_) => None,
+                    None => public_notice(config, &state),
+                };
+                let mut output = serde_json::Map::new();
+                if let Some(context) = context {
+                    output.insert(
+                        "hookSpecificOutput".to_string(),
+                        serde_json::json!({
+                            "hookEventName": "UserPromptSubmit",
+                            "additionalContext": context
+                        }),
+                    );
+                }
+                // 送らないプロンプトはClaudeには渡るので、送っていないことをユーザーに知らせる
+                if private {
+                    output.insert(
+                        "systemMessage".to_string(),
+                        "aloud-code: this prompt and its response won't be posted".into(),
+                    );
+                }
+                println!("{}", serde_json::Value::Object(output));
+            } else {
+                println!("{{}}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// UserPromptSubmit hookの出力: コマンドをClaudeに渡さず、結果だけをユーザーに表示する
+fn block(reason: &str) {
+    let decision = serde_json::json!({
+        "decision": "block",
+        "reason": reason
+    });
+    println!("{}", decision);
+}
+
+/// 配信中のプロンプトに添える、会話が公開されていることの注意書き（伝える公開先がなければ None）
+fn public_notice(config: &Config, state: &SessionState) -> Option<String> {
+    let audiences: Vec<String> = config
+        .destinations()
+        .into_iter()
+        .filter(|(name, _)| {
+            state
+                .destinations
+                .as_ref()
+                .is_none_or(|names| names.iter().any(|n| n == name))
+        })
+        .filter_map(|(name, webhook)| webhook.audience(name))
+        .collect();
+    if audiences.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "aloud-code is broadcasting this session: every prompt and every response you write is posted to {}. \
+         Treat the conversation as public. Do not print secrets such as API keys, passwords, tokens or private keys; \
+         if you need to refer to one, mask it.",
+        audiences.join(", ")
+    ))
+}
+
+/// ONにしたときの確認メッセージ（送信先・終了時刻・プレビューの書き出し先）
+fn started_notice(session_id: &str, state: &SessionState) -> Result<String> {
+    let mut details = Vec::new();
+    if let Some(names) = &state.destinations {
+        details.push(format!("to {}", names.join(", ")));
+    }
+    if let Some(expires_at) = state.expires_at {
+        details.push(format!("until {}", expires_at.format("%H:%M UTC")));
+    }
+    if state.preview {
+        match DryRun::for_session(session_id, state)? {
+            Some(DryRun::File(path)) => details.push(format!("writing to {}", path.display())),
+            Some(DryRun::Stderr) => details.push("writing to stderr".to_string()),
+            None => {}
         }
-        _ => {}
+    }
+    let mode = if state.preview {
+        "preview"
+    } else {
+        "streaming"
+    };
+    Ok(if details.is_empty() {
+        format!("aloud-code: {} on", mode)
+    } else {
+        format!("aloud-code: {} on ({})", mode, details.join(", "))
+    })
+}
+
+/// `/aloud-code:status` で表示する内容（送信先・送信件数・失敗）
+fn status(
+    session_id: &str,
+    state: Option<&SessionState>,
+    config: Option<&Config>,
+) -> Result<String> {
+    let Some(state) = state else {
+        return Ok("aloud-code: streaming off".to_string());
+    };
+    let mut lines = vec![started_notice(session_id, state)?];
+    if let (None, Some(config)) = (&state.destinations, config) {
+        let names: Vec<&str> = config
+            .destinations()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        lines.push(format!(
+            "destinations: {}",
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        ));
+    }
+    lines.push(format!(
+        "{}: {} prompt{}, {} response{}",
+        if state.preview { "written" } else { "sent" },
+        state.prompts,
+        plural(state.prompts),
+        state.responses,
+        plural(state.responses)
+    ));
+    if state.skip_next {
+        lines.push("next prompt: not posted (/aloud-code:skip)".to_string());
+    }
+    lines.push(match (state.failures, &state.last_failure) {
+        (0, _) => "failures: none".to_string(),
+        (n, Some(last)) => format!("failures: {} (last: {})", n, last),
+        (n, None) => format!("failures: {}", n),
+    });
+    Ok(lines.join("\n"))
+}
+
+/// トグルコマンドで指定された送信先がすべて設定にあるか確認する
+fn check_destinations(names: &[String], config: &Config) -> Result<()> {
+    let configured: Vec<&str> = config
+        .destinations()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let unknown: Vec<&str> = names
+        .iter()
+        .map(|n| n.as_str())
+        .filter(|n| !configured.contains(n))
+        .collect();
+    if !unknown.is_empty() {
+        anyhow::bail!(
+            "unknown destination {} (configured: {})",
+            unknown.join(", "),
+            if configured.is_empty() {
+                "none".to_string()
+            } else {
+                configured.join(", ")
+            }
+        );
+    }
+    Ok(())
+}
+
+/// ONにしたついでに、期限切れのまま残っている他のセッションの状態を片付ける
+fn prune_sessions(store: &SessionStore, config: &Config) {
+    if let Err(e) = store.prune(config.sessions.max_idle(), Utc::now()) {
+        eprintln!("aloud-code: 期限切れのセッションの削除に失敗: {}", e);
+    }
+}
+
+/// 設定で有効なら、配信の開始・終了を別プロセスで告知する
+fn announce(config: &Config, announcement: Announcement) {
+    if !Announcement::enabled(config, &announcement.state) {
+        return;
+    }
+    if let Err(e) = announcement.spawn() {
+        eprintln!("aloud-code: 告知の送信に失敗: {}", e);
+    }
+}
+
+/// SessionEnd hook: 終了したセッションの状態を削除する（同じIDで再開されてもOFFから始まる）
+pub fn handle_session_end() -> Result<()> {
+    let input = HookInput::from_stdin()?;
+    let session_id = input.session_id.as_deref().unwrap_or("");
+    let store = SessionStore::open()?;
+    let state = store.load(session_id)?;
+    store.deactivate(session_id)?;
+    if let (Some(state), Ok(config)) =
+        (state, Config::load_for(input.cwd.as_deref().map(Path::new)))
+    {
+        announce(
+            &config,
+            Announcement::new(AnnouncementKind::Stopped, &input, state)
+                .with_reason("session ended"),
+        );
     }
     println!("{{}}");
     Ok(())
 }
 
+/// 送信先の設定（形式・テンプレート・チャンネル）に応じてペイロードを組み立てる
+pub fn build_payload(
+    webhook: &WebhookConfig,
+    message: &Message,
+    templates: &Templates,
+) -> Result<Payload> {
+    match webhook.format {
+        PayloadFormat::Event => Ok(serde_json::to_value(EventEnvelope::new(message))?.into()),
+        PayloadFormat::CloudEvents => {
+            let event = CloudEvent::new(message);
+            Ok(match webhook.cloudevents_mode.unwrap_or_default() {
+                CloudEventsMode::Structured => event.structured(),
+                CloudEventsMode::Binary => event.binary(),
+            })
+        }
+        PayloadFormat::Slack => {
+            let payload = match webhook.template.as_deref() {
+                Some(name) => {
+                    templates.render(name, message.kind, &TemplateContext::new(message))?
+                }
+                None => formatter::format_kind(message.kind, message.text, message.ctx),
+            };
+            Ok(formatter::with_channel(payload, webhook.channel.as_deref()).into())
+        }
+    }
+}
+
+/// 送信先の設定（URL・ヘッダー・署名用シークレット・証明書）を解決して送信クライアントを作る
+pub fn sender_for(http: &HttpConfig, webhook: &WebhookConfig) -> Result<WebhookSender> {
+    let options = ClientOptions {
+        proxy: http.resolve_proxy()?,
+        ca_certs: http
+            .ca_certs
+            .iter()
+            .map(|p| config::expand_home(p))
+            .collect(),
+        client_identity: webhook.client_identity(),
+    };
+    let mut sender = WebhookSender::with_options(webhook.resolve_url()?, &options)?
+        .with_headers(webhook.resolve_headers()?);
+    if let Some(secret) = webhook.resolve_secret()? {
+        sender = sender.with_secret(secret);
+    }
+    Ok(sender)
+}
+
+/// テンプレートを使う送信先がある場合のみgit情報を取得する
+pub fn detect_git(destinations: &[(&str, &WebhookConfig)], cwd: &str) -> Option<GitInfo> {
+    destinations
+        .iter()
+        .any(|(_, w)| w.template.is_some())
+        .then(|| GitInfo::detect(cwd))
+        .flatten()
+}
+
+/// 送信先に届く内容を、送信せずに確認できる形にする（URLや認証ヘッダーは含めない）
+pub fn preview(
+    name: &str,
+    webhook: &WebhookConfig,
+    message: &Message,
+    templates: &Templates,
+) -> Result<String> {
+    let mut text = format!(
+        "--- #{} {} → {} ({})\n",
+        message.sequence,
+        message.kind.event_name(),
+        name,
+        message
+            .timestamp
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+    );
+    match webhook.kind {
+        DestinationKind::Webhook => {
+            let payload = build_payload(webhook, message, templates)?;
+            for (name, value) in &payload.headers {
+                text.push_str(&format!("{}: {}\n", name, value));
+            }
+            text.push_str(&serde_json::to_string_pretty(&payload.body)?);
+            text.push('\n');
+        }
+        DestinationKind::Archive => text.push_str("(append to local archive)\n"),
+        DestinationKind::Markdown => text.push_str("(append to local transcript)\n"),
+    }
+    Ok(text)
+}
+
+/// 1つの送信先にメッセージを届ける
+pub async fn deliver(
+    config: &Config,
+    webhook: &WebhookConfig,
+    message: &Message<'_>,
+    templates: &Templates,
+) -> Result<()> {
+    match webhook.kind {
+        DestinationKind::Webhook => {
+            let payload = build_payload(webhook, message, templates)?;
+            sender_for(&config.http, webhook)?.send(payload).await
+        }
+        DestinationKind::Archive => {
+            let dir = archive::archive_dir()?;
+            let path = archive::append(&dir, message)?;
+            archive::prune(
+                &dir,
+                webhook.retention(),
+                message.timestamp.date_naive(),
+                &path,
+            )
+        }
+        DestinationKind::Markdown => {
+            transcript::append(&webhook.transcript_dir()?, message).map(|_| ())
+        }
+    }
+}
+
 pub async fn handle_hook(event: &str) -> Result<()> {
     let input = HookInput::from_stdin()?;
     let session_id = input.session_id.as_deref().unwrap_or("");
 
-    if !config::is_active(session_id) {
-        println!("{{}}");
-        return Ok(());
-    }
+    let store = SessionStore::open()?;
+    let state = match store.load(session_id) {
+        Ok(Some(state)) => state,
+        Ok(None) => {
+            println!("{{}}");
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!(
+                "aloud-code: セッションの状態を読めないため送信しません: {:#}",
+                e
+            );
+            println!("{{}}");
+            return Ok(());
+        }
+    };
 
-    let config = Config::load()?;
-    let webhook_url = match &config.webhook.url {
-        Some(url) if !url.is_empty() => url.clone(),
-        _ => {
+    let cwd = input.cwd.as_deref().map(Path::new);
+    let config = match Config::load_for(cwd).and_then(|c| c.validate().map(|_| c)) {
+        Ok(config) => config,
+        Err(e) => {
+            // 設定不備で黙って何も送られない状態を避けるため、理由を必ず出力する
+            eprintln!("aloud-code: 設定ファイルが不正なため送信しません: {:#}", e);
             println!("{{}}");
             return Ok(());
         }
     };
+    if store.expire(session_id, &state, config.sessions.max_idle(), Utc::now())? {
+        eprintln!(
+            "aloud-code: 長期間操作のなかったセッション {} をOFFにしました",
+            session_id
+        );
+        println!("{{}}");
+        return Ok(());
+    }
+    let mut destinations = config.destinations();
+    if let Some(names) = &state.destinations {
+        destinations.retain(|(name, _)| names.iter().any(|n| n == name));
+    }
+    if destinations.is_empty() {
+        println!("{{}}");
+        return Ok(());
+    }
 
-    let ctx = input.to_session_context();
-    let sender = WebhookSender::new(webhook_url);
+    let mut ctx = input.to_session_context();
+    ctx.labels = config.labels.clone();
+    ctx.title = state.title.clone();
+    ctx.tags = state.tags.clone();
+    let redactor = Redactor::new(&config.redact)?;
 
-    match event {
+    let message = match event {
         "user-prompt" => {
             let prompt = input.prompt.as_deref().unwrap_or("");
             // トグルコマンドはhandle_toggleで処理済みのためスキップ
-            if !prompt.is_empty() && !is_toggle_command(prompt) {
-                let payload = formatter::format_user_message(prompt, &ctx);
-                sender.send(payload).await?;
+            if prompt.is_empty() || is_command(prompt) {
+                None
+            } else if config.sessions.is_private(prompt) || state.prompt_is_private(prompt) {
+                // 送らないプロンプト（`private_prefix` 付き、または `/aloud-code:skip` の直後）。
+                // 同期のトグルhookと並行して動くので、そちらが記録する前後どちらの状態でも送らない
+                None
+            } else {
+                Some((MessageKind::User, redactor.redact(prompt).into_owned()))
             }
         }
         "stop" => {
             let message = input.last_assistant_message.as_deref().unwrap_or("");
-            if !message.is_empty() {
-                let payload = formatter::format_assistant_message(message, &ctx);
-                sender.send(payload).await?;
+            // 送らなかったプロンプトへの応答も送らない
+            let private = store.end_turn(session_id)? != Some(false);
+            if !message.is_empty() && !private {
+                Some((
+                    MessageKind::Assistant,
+                    redactor.redact(message).into_owned(),
+                ))
+            } else {
+                None
             }
         }
         unknown => {
             eprintln!("aloud-code: 未知のhookイベント: {}", unknown);
+            None
+        }
+    };
+
+    if let Some((kind, text)) = message {
+        // 読み込んだ後にOFFにされていたら送らない
+        let Some(sequence) = store.record(session_id, kind)? else {
+            println!("{{}}");
+            return Ok(());
+        };
+        let templates = Templates::new(&config.templates)?;
+        let git = detect_git(&destinations, &ctx.cwd);
+        let message = Message {
+            kind,
+            text: &text,
+            ctx: &ctx,
+            timestamp: Utc::now(),
+            sequence,
+            git,
+        };
+
+        if let Some(dry_run) = DryRun::for_session(session_id, &state)? {
+            for (name, webhook) in destinations {
+                dry_run.write(&preview(name, webhook, &message, &templates)?)?;
+            }
+            println!("{{}}");
+            return Ok(());
+        }
+
+        let mut last_err = None;
+        for (name, webhook) in destinations {
+            if let Err(e) = deliver(&config, webhook, &message, &templates).await {
+                // 1つの送信先の失敗で他の送信先への送信を止めない
+                eprintln!("aloud-code: 送信先 {} への送信に失敗: {}", name, e);
+                if let Err(e) = store.record_failure(session_id, name, &format!("{:#}", e)) {
+                    eprintln!("aloud-code: 送信の失敗を記録できません: {}", e);
+                }
+                last_err = Some(e);
+            }
+        }
+        if let Some(e) = last_err {
+            return Err(e);
         }
     }
 
@@ -180,12 +879,256 @@ mod tests {
     }
 
     #[test]
-    fn test_is_toggle_command() {
-        assert!(is_toggle_command("/aloud-code:on"));
-        assert!(is_toggle_command("/aloud-code:off"));
-        assert!(is_toggle_command("  /aloud-code:on  ")); // 前後スペース
-        assert!(!is_toggle_command("hello"));
-        assert!(!is_toggle_command("/aloud-code:on extra")); // 余分なテキスト
-        assert!(!is_toggle_command(""));
+    fn test_parse_toggle() {
+        assert_eq!(Command::parse("/aloud-code:off"), Some(Command::Off));
+        assert_eq!(
+            Command::parse(" /aloud-code:on "),
+            Some(Command::On {
+                preview: false,
+                title: None,
+                tags: vec![],
+                destinations: vec![],
+                duration: None
+            })
+        );
+        assert_eq!(
+            Command::parse("/aloud-code:preview 1h30m"),
+            Some(Command::On {
+                preview: true,
+                title: None,
+                tags: vec![],
+                destinations: vec![],
+                duration: Some(chrono::Duration::minutes(90))
+            })
+        );
+        assert_eq!(
+            Command::parse("/aloud-code:on design-review 30m archive"),
+            Some(Command::On {
+                preview: false,
+                title: None,
+                tags: vec![],
+                destinations: vec!["design-review".to_string(), "archive".to_string()],
+                duration: Some(chrono::Duration::minutes(30))
+            })
+        );
+        assert_eq!(
+            Command::parse(r#"/aloud-code:on "Fix flaky auth tests" #backend #auth 1h"#),
+            Some(Command::On {
+                preview: false,
+                title: Some("Fix flaky auth tests".to_string()),
+                tags: vec!["backend".to_string(), "auth".to_string()],
+                destinations: vec![],
+                duration: Some(chrono::Duration::hours(1))
+            })
+        );
+        // 引数が誤っていても普通のプロンプトにはしない
+        let invalid = |prompt: &str| {
+            assert!(
+                matches!(Command::parse(prompt), Some(Command::Invalid { .. })),
+                "{}",
+                prompt
+            );
+        };
+        invalid(r#"/aloud-code:on "a" "b""#);
+        invalid(r#"/aloud-code:on "unclosed"#);
+        invalid("/aloud-code:on 90m 2h");
+        invalid("/aloud-code:preview 1h 1h");
+        invalid("/aloud-code:off extra");
+        assert_eq!(Command::parse("hello /aloud-code:on"), None);
+        assert_eq!(Command::parse("/aloud-code:onward"), None);
+    }
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(
+            Command::parse(" /aloud-code:status "),
+            Some(Command::Status)
+        );
+        assert_eq!(
+            Command::parse("/aloud-code:status please"),
+            Some(Command::Invalid {
+                usage: "/aloud-code:status (takes no arguments)"
+            })
+        );
+        assert_eq!(Command::parse("/aloud-code:statusx"), None);
+    }
+
+    #[test]
+    fn test_parse_skip() {
+        assert_eq!(Command::parse("/aloud-code:skip"), Some(Command::Skip));
+        assert!(matches!(
+            Command::parse("/aloud-code:skip 2"),
+            Some(Command::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_note() {
+        assert_eq!(
+            Command::parse("/aloud-code:note Back in 10 minutes"),
+            Some(Command::Note("Back in 10 minutes".to_string()))
+        );
+        // 引用符や改行はそのまま残す
+        assert_eq!(
+            Command::parse("/aloud-code:note  \"quoted\n  two lines "),
+            Some(Command::Note("\"quoted\n  two lines".to_string()))
+        );
+        // テキストのないメモは普通のプロンプトにせず、使い方を表示する
+        for prompt in ["/aloud-code:note", "/aloud-code:note   \n "] {
+            assert_eq!(
+                Command::parse(prompt),
+                Some(Command::Invalid {
+                    usage: "/aloud-code:note <text>"
+                }),
+                "{:?}",
+                prompt
+            );
+        }
+        assert_eq!(Command::parse("/aloud-code:notes hi"), None);
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(
+            status("s", None, None).unwrap(),
+            "aloud-code: streaming off"
+        );
+        let config = Config::parse(
+            "[webhook]\nurl = \"https://example.com/a\"\n\n[destinations.review]\nurl = \"https://example.com/b\"\n",
+        )
+        .unwrap();
+        let state = SessionState {
+            prompts: 1,
+            responses: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            status("s", Some(&state), Some(&config)).unwrap(),
+            "aloud-code: streaming on\ndestinations: default, review\nsent: 1 prompt, 2 responses\nfailures: none"
+        );
+        let state = SessionState {
+            destinations: Some(vec!["review".to_string()]),
+            failures: 3,
+            last_failure: Some("review: HTTP 500 (12:03 UTC)".to_string()),
+            ..state
+        };
+        assert_eq!(
+            status("s", Some(&state), Some(&config)).unwrap(),
+            "aloud-code: streaming on (to review)\nsent: 1 prompt, 2 responses\nfailures: 3 (last: review: HTTP 500 (12:03 UTC))"
+        );
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            split_args(r#"  "Fix  it " #tag review"#),
+            Some(vec![
+                Arg::Quoted("Fix  it".to_string()),
+                Arg::Word("#tag".to_string()),
+                Arg::Word("review".to_string()),
+            ])
+        );
+        assert_eq!(split_args(""), Some(vec![]));
+        assert_eq!(split_args("\""), None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90m"), Some(chrono::Duration::minutes(90)));
+        assert_eq!(parse_duration("2h"), Some(chrono::Duration::hours(2)));
+        assert_eq!(parse_duration("1h15m"), Some(chrono::Duration::minutes(75)));
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("90"), None, "単位がない");
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("1d"), None);
+        assert_eq!(parse_duration("99999999999999h"), None);
+    }
+
+    #[test]
+    fn test_dry_run_parse() {
+        assert_eq!(DryRun::parse(""), None);
+        assert_eq!(DryRun::parse("0"), None);
+        assert_eq!(DryRun::parse("false"), None);
+        assert_eq!(DryRun::parse("1"), Some(DryRun::Stderr));
+        assert_eq!(DryRun::parse("stderr"), Some(DryRun::Stderr));
+        assert_eq!(
+            DryRun::parse("/tmp/aloud.log"),
+            Some(DryRun::File(PathBuf::from("/tmp/aloud.log")))
+        );
+    }
+
+    #[test]
+    fn test_started_notice() {
+        let state = SessionState::default();
+        assert_eq!(
+            started_notice("s", &state).unwrap(),
+            "aloud-code: streaming on"
+        );
+        let state = SessionState {
+            destinations: Some(vec!["team".to_string(), "review".to_string()]),
+            expires_at: Some("2026-01-02T15:30:00Z".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            started_notice("s", &state).unwrap(),
+            "aloud-code: streaming on (to team, review, until 15:30 UTC)"
+        );
+    }
+
+    #[test]
+    fn test_public_notice() {
+        let config = Config::parse(
+            r#"
+[webhook]
+url = "https://hooks.slack.com/services/x"
+notice = "the #eng Slack channel"
+
+[destinations.ingest]
+url = "https://example.com/events"
+format = "event"
+
+[destinations.internal]
+url = "https://example.com/internal"
+notice = ""
+
+[destinations.log]
+type = "archive"
+"#,
+        )
+        .unwrap();
+        let notice = public_notice(&config, &SessionState::default()).unwrap();
+        assert!(
+            notice.contains("posted to the #eng Slack channel, the \"ingest\" webhook."),
+            "公開先の一覧が違う: {}",
+            notice
+        );
+        assert!(notice.contains("Do not print secrets"));
+
+        // 公開先として伝えない送信先だけなら注意書きは付けない
+        let state = SessionState {
+            destinations: Some(vec!["internal".to_string(), "log".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(public_notice(&config, &state), None);
+    }
+
+    #[test]
+    fn test_is_command() {
+        assert!(is_command("/aloud-code:on"));
+        assert!(is_command("/aloud-code:off"));
+        assert!(is_command("/aloud-code:preview"));
+        assert!(is_command("  /aloud-code:on  ")); // 前後スペース
+        assert!(!is_command("hello"));
+        assert!(!is_command("/aloud-code:onextra"));
+        assert!(is_command("/aloud-code:off extra")); // 余分なテキストは使い方を表示する
+        assert!(is_command("/aloud-code:on 90m"));
+        assert!(is_command("/aloud-code:preview 1h"));
+        assert!(is_command("/aloud-code:on design-review 90m"));
+        assert!(is_command("/aloud-code:on 90m 2h"));
+        assert!(is_command("/aloud-code:off 90m"));
+        assert!(!is_command(""));
+        assert!(is_command("/aloud-code:status"));
+        assert!(is_command("/aloud-code:skip"));
+        assert!(is_command("/aloud-code:note hello"));
     }
 }
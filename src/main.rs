@@ -1,17 +1,50 @@
+mod announce;
+mod archive;
+mod cli;
+mod cloudevents;
 mod config;
+mod event;
 mod formatter;
+mod git;
 mod hook;
+mod html;
+mod layers;
+mod redact;
+mod secrets;
+mod session;
+mod session_log;
+mod signature;
+mod templates;
+mod transcript;
 mod webhook;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args: Vec<String> = std::env::args().collect();
-    if args.get(1).map(|s| s.as_str()) == Some("hook") {
-        let event = args.get(2).map(|s| s.as_str()).unwrap_or("");
-        match event {
-            "toggle" => hook::handle_toggle().await?,
-            other => hook::handle_hook(other).await?,
+    match args.get(1).map(|s| s.as_str()) {
+        Some("hook") => {
+            let event = args.get(2).map(|s| s.as_str()).unwrap_or("");
+            match event {
+                "toggle" => hook::handle_toggle().await?,
+                "session-end" => hook::handle_session_end()?,
+                "announce" => announce::handle_announce().await?,
+                other => hook::handle_hook(other).await?,
+            }
         }
+        Some("test") => cli::run_test().await?,
+        Some("replay") => cli::replay(&args[2..]).await?,
+        Some("export") => cli::export(&args[2..])?,
+        Some("sessions") => match args.get(2).map(|s| s.as_str()) {
+            Some("prune") => cli::sessions_prune(&args[3..])?,
+            _ => anyhow::bail!("usage: aloud-code sessions prune [--days N]"),
+        },
+        Some("config") => match args.get(2).map(|s| s.as_str()) {
+            Some("init") => cli::config_init(&args[3..])?,
+            Some("validate") => cli::config_validate()?,
+            Some("show") => cli::config_show(&args[3..])?,
+            _ => anyhow::bail!("usage: aloud-code config <init|validate|show [--effective]>"),
+        },
+        _ => {}
     }
     Ok(())
 }
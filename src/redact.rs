@@ -0,0 +1,86 @@
+use anyhow::Result;
+use regex::Regex;
+use std::borrow::Cow;
+
+use crate::config::RedactConfig;
+
+const DEFAULT_REPLACEMENT: &str = "[REDACTED]";
+
+/// 設定された正規表現に一致する部分を送信前に置き換える
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    replacement: String,
+}
+
+impl Redactor {
+    pub fn new(config: &RedactConfig) -> Result<Self> {
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|p| Regex::new(p).map_err(|e| anyhow::anyhow!("`{}`: {}", p, e)))
+            .collect::<Result<Vec<_>>>()?;
+        let replacement = config
+            .replacement
+            .clone()
+            .unwrap_or_else(|| DEFAULT_REPLACEMENT.to_string());
+        Ok(Redactor {
+            patterns,
+            replacement,
+        })
+    }
+
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut result = Cow::Borrowed(text);
+        for pattern in &self.patterns {
+            // `$1` などが置換文字列として解釈されないようにそのまま埋め込む
+            let replaced = pattern.replace_all(&result, regex::NoExpand(&self.replacement));
+            if let Cow::Owned(s) = replaced {
+                result = Cow::Owned(s);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor(patterns: &[&str], replacement: Option<&str>) -> Redactor {
+        Redactor::new(&RedactConfig {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            replacement: replacement.map(|r| r.to_string()),
+        })
+        .expect("Redactor作成失敗")
+    }
+
+    #[test]
+    fn test_no_patterns_returns_input() {
+        let r = redactor(&[], None);
+        assert!(matches!(r.redact("hello"), Cow::Borrowed("hello")));
+    }
+
+    #[test]
+    fn test_redact_multiple_patterns() {
+        let r = redactor(&["sk-[A-Za-z0-9]+", "(?i)password=\\S+"], None);
+        assert_eq!(
+            r.redact("key sk-abc123 and PASSWORD=hunter2"),
+            "key [REDACTED] and [REDACTED]"
+        );
+    }
+
+    #[test]
+    fn test_custom_replacement_is_literal() {
+        let r = redactor(&["secret"], Some("$1***"));
+        assert_eq!(r.redact("a secret b"), "a $1*** b");
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_error() {
+        let result = Redactor::new(&RedactConfig {
+            patterns: vec!["(unclosed".to_string()],
+            replacement: None,
+        });
+        assert!(result.is_err());
+    }
+}
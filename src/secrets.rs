@@ -0,0 +1,257 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// `url_command` の実行タイムアウト
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `url_command` の実行結果（プロセス内で1度だけ実行する）
+static COMMAND_CACHE: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+
+/// `${env:NAME}` を環境変数の値に置き換える
+pub fn interpolate(value: &str) -> Result<String> {
+    interpolate_with(value, |name| std::env::var(name).ok())
+}
+
+fn interpolate_with(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            // 値そのものは秘密情報の可能性があるためメッセージに含めない
+            .ok_or_else(|| anyhow::anyhow!("`${{` が閉じられていません"))?;
+        let expr = &after[..end];
+        let name = expr
+            .strip_prefix("env:")
+            .filter(|n| !n.is_empty())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "未対応の参照です（`${{env:NAME}}` のみ使えます）: ${{{}}}",
+                    expr
+                )
+            })?;
+        let resolved = lookup(name)
+            .ok_or_else(|| anyhow::anyhow!("環境変数 {} が設定されていません", name))?;
+        out.push_str(&resolved);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// 値を解決せずに `${...}` の構文だけを検証する
+pub fn check_interpolation(value: &str) -> Result<()> {
+    interpolate_with(value, |_| Some(String::new())).map(|_| ())
+}
+
+/// 環境変数参照を含まない値かどうか
+pub fn is_literal(value: &str) -> bool {
+    !value.contains("${")
+}
+
+/// シェルコマンドを実行し、標準出力（前後の空白を除く）を返す。結果はプロセス内でキャッシュする
+pub fn run_command(command: &str) -> Result<String> {
+    run_command_with_timeout(command, COMMAND_TIMEOUT)
+}
+
+fn run_command_with_timeout(command: &str, timeout: Duration) -> Result<String> {
+    let cache = COMMAND_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(cached) = cache.lock().unwrap().get(command) {
+        return Ok(cached.clone());
+    }
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("コマンドの起動に失敗 ({}): {}", command, e))?;
+
+    // パイプが一杯になってコマンドが止まらないよう、実行中から出力を読む
+    let (closed_tx, closed_rx) = mpsc::channel();
+    let stdout = read_to_end(child.stdout.take(), closed_tx.clone());
+    let stderr = read_to_end(child.stderr.take(), closed_tx);
+
+    // 標準出力・標準エラーの両方が閉じる（普通はコマンドが終了する）まで待つ
+    let deadline = Instant::now() + timeout;
+    for _ in 0..2 {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if closed_rx.recv_timeout(remaining).is_err() {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!(
+                "コマンドが{}秒以内に終了しませんでした: {}",
+                timeout.as_secs_f32(),
+                command
+            );
+        }
+    }
+    let status = child.wait()?;
+
+    let stdout = String::from_utf8(stdout.join().unwrap_or_default())
+        .map_err(|_| anyhow::anyhow!("コマンドの出力がUTF-8ではありません: {}", command))?;
+    if !status.success() {
+        let stderr = String::from_utf8_lossy(&stderr.join().unwrap_or_default()).into_owned();
+        anyhow::bail!(
+            "コマンドが失敗しました ({}): {} {}",
+            status,
+            command,
+            stderr.trim()
+        );
+    }
+
+    let value = stdout.trim().to_string();
+    if value.is_empty() {
+        anyhow::bail!("コマンドの出力が空です: {}", command);
+    }
+    cache
+        .lock()
+        .unwrap()
+        .insert(command.to_string(), value.clone());
+    Ok(value)
+}
+
+/// パイプを別スレッドで最後まで読み、閉じたら `closed` に知らせる
+fn read_to_end(
+    pipe: Option<impl Read + Send + 'static>,
+    closed: mpsc::Sender<()>,
+) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        let _ = closed.send(());
+        buf
+    })
+}
+
+/// 秘密情報を平文で含むファイルがグループ・他人から読める場合の警告文を返す
+#[cfg(unix)]
+pub fn readable_by_others_warning(path: &Path) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(path).ok()?.permissions().mode();
+    if mode & 0o044 == 0 {
+        return None;
+    }
+    Some(format!(
+        "{} はグループ・他のユーザーから読めるパーミッション ({:o}) ですが、Webhook URLやシークレットを平文で含んでいます。\
+         `chmod 600` するか、`${{env:NAME}}` や url_command を使ってください",
+        path.display(),
+        mode & 0o777
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn readable_by_others_warning(_path: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "SLACK_WEBHOOK" => Some("https://hooks.slack.com/services/T/B/X".to_string()),
+            "TOKEN" => Some("abc".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        assert_eq!(
+            interpolate_with("${env:SLACK_WEBHOOK}", lookup).unwrap(),
+            "https://hooks.slack.com/services/T/B/X"
+        );
+        assert_eq!(
+            interpolate_with("https://example.com/${env:TOKEN}/x", lookup).unwrap(),
+            "https://example.com/abc/x"
+        );
+        assert_eq!(
+            interpolate_with("https://example.com", lookup).unwrap(),
+            "https://example.com"
+        );
+    }
+
+    #[test]
+    fn test_interpolate_errors() {
+        assert!(interpolate_with("${env:MISSING}", lookup).is_err());
+        assert!(interpolate_with("${env:TOKEN", lookup).is_err());
+        assert!(interpolate_with("${file:/etc/passwd}", lookup).is_err());
+        assert!(interpolate_with("${env:}", lookup).is_err());
+    }
+
+    #[test]
+    fn test_check_interpolation_does_not_need_env() {
+        assert!(check_interpolation("${env:NOT_SET_ANYWHERE_12345}").is_ok());
+        assert!(check_interpolation("${nope}").is_err());
+    }
+
+    #[test]
+    fn test_run_command_trims_output() {
+        let value = run_command("echo '  https://example.com/secret  '").unwrap();
+        assert_eq!(value, "https://example.com/secret");
+    }
+
+    #[test]
+    fn test_run_command_reads_large_output() {
+        // パイプのバッファより大きい出力でも止まらない
+        let value = run_command_with_timeout(
+            "head -c 200000 /dev/zero | tr '\\0' a; echo x >&2",
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(value.len(), 200000);
+    }
+
+    #[test]
+    fn test_run_command_is_cached() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let counter = temp_dir.path().join("count");
+        let command = format!(
+            "echo x >> '{}'; echo https://example.com",
+            counter.display()
+        );
+
+        run_command(&command).unwrap();
+        run_command(&command).unwrap();
+        let runs = std::fs::read_to_string(&counter).unwrap();
+        assert_eq!(runs.lines().count(), 1, "2回実行された");
+    }
+
+    #[test]
+    fn test_run_command_failure_and_timeout() {
+        assert!(run_command("exit 3").is_err());
+        assert!(run_command("true").is_err(), "空の出力はエラー");
+
+        let started = Instant::now();
+        let result = run_command_with_timeout("sleep 5", Duration::from_millis(100));
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_readable_by_others_warning() {
+        use std::os::unix::fs::PermissionsExt;
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::set_permissions(file.path(), std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(readable_by_others_warning(file.path()).is_none());
+
+        std::fs::set_permissions(file.path(), std::fs::Permissions::from_mode(0o644)).unwrap();
+        let warning = readable_by_others_warning(file.path()).expect("警告がない");
+        assert!(warning.contains("644"), "{}", warning);
+    }
+}
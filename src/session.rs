@@ -0,0 +1,775 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::archive::file_stem;
+use crate::config;
+use crate::formatter::MessageKind;
+
+/// 有効なセッションごとに `<sessions_dir>/<session_id>` に保存する状態
+///
+/// 以前の形式（空のフラグファイル、または直前の通し番号だけを書いたファイル）もそのまま読める
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionState {
+    /// ONにした時刻（以前の形式から読んだ場合は不明）
+    pub activated_at: Option<DateTime<Utc>>,
+    /// 最後にメッセージを処理した時刻
+    pub last_activity_at: Option<DateTime<Utc>>,
+    /// 時間を指定してONにした場合に自動でOFFになる時刻
+    pub expires_at: Option<DateTime<Utc>>,
+    /// 送信せずにペイロードを書き出すプレビューモード
+    pub preview: bool,
+    /// `/aloud-code:on "タイトル"` で付けたタイトル
+    pub title: Option<String>,
+    /// `/aloud-code:on #タグ` で付けたタグ（`#` は含まない）
+    pub tags: Vec<String>,
+    /// このセッションで使う送信先の名前（未指定なら設定されたすべて）
+    pub destinations: Option<Vec<String>>,
+    /// スレッドにまとめて投稿する場合の親メッセージの `ts`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slack_thread_ts: Option<String>,
+    pub prompts: u64,
+    pub responses: u64,
+    /// 直前に送ったメッセージの通し番号（ONにした直後は0）
+    pub last_sequence: u64,
+    /// `/aloud-code:skip` で次のプロンプトとその応答を送らない
+    pub skip_next: bool,
+    /// 同期のUserPromptSubmit hookが始めたターンの数（ターン番号は1始まり）
+    pub turns: u64,
+    /// Stop hookが応答を処理したターンの番号
+    pub turns_ended: u64,
+    /// 送らないターン（応答を処理し終えたものは消す）
+    pub private_turns: Vec<PrivateTurn>,
+    /// 送信に失敗した回数（送信先ごとに数える）
+    pub failures: u64,
+    /// 最後に失敗した送信先とエラー
+    pub last_failure: Option<String>,
+}
+
+/// 送らないと決めたターン
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrivateTurn {
+    pub turn: u64,
+    /// プロンプトのSHA-256（非同期のhookが自分のプロンプトの記録かを確かめるため。本文は保存しない）
+    pub prompt_sha256: String,
+}
+
+fn prompt_sha256(prompt: &str) -> String {
+    hex::encode(Sha256::digest(prompt.as_bytes()))
+}
+
+impl SessionState {
+    fn parse(content: &str) -> Result<Self> {
+        let content = content.trim();
+        if content.is_empty() {
+            return Ok(SessionState::default());
+        }
+        if let Ok(last_sequence) = content.parse() {
+            return Ok(SessionState {
+                last_sequence,
+                ..Default::default()
+            });
+        }
+        Ok(serde_json::from_str(content)?)
+    }
+
+    /// 非同期のUserPromptSubmit hookから: このプロンプトを送らないか
+    ///
+    /// 同期hookの記録（最新のターン）がこのプロンプトのものなら、それに従う。
+    /// まだ記録されていなければ、同期hookが使う `/aloud-code:skip` の指定が残っている
+    pub fn prompt_is_private(&self, prompt: &str) -> bool {
+        let sha256 = prompt_sha256(prompt);
+        self.skip_next
+            || self
+                .private_turns
+                .iter()
+                .any(|t| t.turn == self.turns && t.prompt_sha256 == sha256)
+    }
+
+    /// 指定された時間が過ぎたか
+    pub fn time_is_up(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// 期限切れで削除したセッション
+#[derive(Debug, PartialEq)]
+pub struct Expired {
+    pub session_id: String,
+    pub last_active: DateTime<Utc>,
+}
+
+/// セッション状態の保存先。書き込みはロックを取ってから一時ファイル経由で置き換える
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn open() -> Result<Self> {
+        Ok(SessionStore::at(config::sessions_dir()?))
+    }
+
+    pub fn at(dir: impl Into<PathBuf>) -> Self {
+        SessionStore { dir: dir.into() }
+    }
+
+    /// セッションの状態を読む。OFFのセッションと、指定された時間が過ぎたセッションは `None`
+    pub fn load(&self, session_id: &str) -> Result<Option<SessionState>> {
+        Ok(self
+            .load_raw(session_id)?
+            .filter(|state| !state.time_is_up(Utc::now())))
+    }
+
+    fn load_raw(&self, session_id: &str) -> Result<Option<SessionState>> {
+        if session_id.is_empty() {
+            return Ok(None);
+        }
+        let path = self.path(session_id);
+        match fs::read_to_string(&path) {
+            Ok(content) => SessionState::parse(&content)
+                .map(Some)
+                .map_err(|e| anyhow::anyhow!("{} を読み込めません: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_active(&self, session_id: &str) -> bool {
+        matches!(self.load(session_id), Ok(Some(_)))
+    }
+
+    /// `state` の設定でセッションをONにする。すでにONでも状態（通し番号など）は振り直す
+    pub fn activate(&self, session_id: &str, state: SessionState) -> Result<()> {
+        // 空のIDでは書いた状態を読み出せない
+        if session_id.is_empty() {
+            anyhow::bail!("セッションIDがないためONにできません");
+        }
+        let _lock = self.lock()?;
+        self.save(
+            session_id,
+            &SessionState {
+                activated_at: Some(Utc::now()),
+                ..state
+            },
+        )
+    }
+
+    /// 指定された時間が過ぎていれば状態を削除し、削除した状態を返す
+    pub fn end_if_time_is_up(
+        &self,
+        session_id: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<SessionState>> {
+        let _lock = self.lock()?;
+        match self.load_raw(session_id)? {
+            Some(state) if state.time_is_up(now) => {
+                self.remove(session_id)?;
+                Ok(Some(state))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn deactivate(&self, session_id: &str) -> Result<()> {
+        if session_id.is_empty() {
+            return Ok(());
+        }
+        let _lock = self.lock()?;
+        self.remove(session_id)
+    }
+
+    /// ONのセッションの状態を書き換える。OFFなら何もせず `None`
+    pub fn update<R>(
+        &self,
+        session_id: &str,
+        f: impl FnOnce(&mut SessionState) -> R,
+    ) -> Result<Option<R>> {
+        let _lock = self.lock()?;
+        let Some(mut state) = self.load(session_id)? else {
+            return Ok(None);
+        };
+        let result = f(&mut state);
+        self.save(session_id, &state)?;
+        Ok(Some(result))
+    }
+
+    /// 最後に操作した時刻。以前の形式の状態では記録がないのでファイルの更新時刻を使う
+    fn last_active(&self, session_id: &str, state: &SessionState) -> Option<DateTime<Utc>> {
+        state.last_activity_at.or(state.activated_at).or_else(|| {
+            let modified = fs::metadata(self.path(session_id)).ok()?.modified().ok()?;
+            Some(modified.into())
+        })
+    }
+
+    /// `max_idle` より長く操作のないセッションなら状態を削除して `true` を返す
+    pub fn expire(
+        &self,
+        session_id: &str,
+        state: &SessionState,
+        max_idle: Option<Duration>,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        let Some(max_idle) = max_idle else {
+            return Ok(false);
+        };
+        match self.last_active(session_id, state) {
+            Some(at) if now - at > max_idle => {
+                self.deactivate(session_id)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// 期限切れのセッションの状態をまとめて削除し、削除したものを返す
+    pub fn prune(&self, max_idle: Option<Duration>, now: DateTime<Utc>) -> Result<Vec<Expired>> {
+        let Some(max_idle) = max_idle else {
+            return Ok(Vec::new());
+        };
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let _lock = self.lock()?;
+        let mut expired = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // ロックファイルと書きかけの一時ファイルは状態ではない
+            if name.starts_with('.') || name.ends_with(".tmp") || !entry.file_type()?.is_file() {
+                continue;
+            }
+            // 読めない状態も、更新時刻で期限切れを判断する
+            let state = self.load_raw(&name).ok().flatten().unwrap_or_default();
+            let Some(last_active) = self.last_active(&name, &state) else {
+                continue;
+            };
+            if now - last_active > max_idle || state.time_is_up(now) {
+                fs::remove_file(entry.path())?;
+                expired.push(Expired {
+                    session_id: name,
+                    last_active,
+                });
+            }
+        }
+        expired.sort_by_key(|e| e.last_active);
+        Ok(expired)
+    }
+
+    /// 送信するメッセージを記録し、セッション内の通し番号を返す（1始まり）
+    pub fn record(&self, session_id: &str, kind: MessageKind) -> Result<Option<u64>> {
+        self.update(session_id, |state| {
+            match kind {
+                MessageKind::User => state.prompts += 1,
+                MessageKind::Assistant => state.responses += 1,
+            }
+            state.last_activity_at = Some(Utc::now());
+            state.last_sequence += 1;
+            state.last_sequence
+        })
+    }
+
+    /// 新しいターンを始め、そのプロンプトを送らないかを決めて記録する（`/aloud-code:skip` の指定はここで使い切る）
+    ///
+    /// 同期のUserPromptSubmit hookから呼ぶ
+    pub fn start_turn(
+        &self,
+        session_id: &str,
+        prompt: &str,
+        private: bool,
+    ) -> Result<Option<bool>> {
+        self.update(session_id, |state| {
+            let private = private || std::mem::take(&mut state.skip_next);
+            state.turns += 1;
+            // 前のターンのStop hookはまだ動いている途中かもしれないが、それより古いターンは
+            // 中断されて応答が来なかったもの
+            state.turns_ended = state.turns_ended.max(state.turns.saturating_sub(2));
+            if private {
+                state.private_turns.push(PrivateTurn {
+                    turn: state.turns,
+                    prompt_sha256: prompt_sha256(prompt),
+                });
+            }
+            let ended = state.turns_ended;
+            state.private_turns.retain(|t| t.turn >= ended);
+            private
+        })
+    }
+
+    /// Stop hookから: 応答を送らないかを返し、応答の来ていない最も古いターンを終える
+    ///
+    /// 非同期のStop hookは次のターンが始まった後に動くこともあり、どのターンの応答かは
+    /// 区別できない。応答を待っているターンのどれかが送らないものなら送らない
+    pub fn end_turn(&self, session_id: &str) -> Result<Option<bool>> {
+        self.update(session_id, |state| {
+            let open = state.turns_ended + 1..=state.turns;
+            let private = if open.is_empty() {
+                // 同じターンの2回目のStop（他のStop hookが続けさせた場合など）
+                state
+                    .private_turns
+                    .iter()
+                    .any(|t| t.turn == state.turns_ended)
+            } else {
+                state.private_turns.iter().any(|t| open.contains(&t.turn))
+            };
+            state.turns_ended = (state.turns_ended + 1).min(state.turns);
+            let ended = state.turns_ended;
+            state.private_turns.retain(|t| t.turn >= ended);
+            private
+        })
+    }
+
+    /// 送信先への送信に失敗したことを記録する
+    pub fn record_failure(&self, session_id: &str, destination: &str, error: &str) -> Result<()> {
+        self.update(session_id, |state| {
+            state.failures += 1;
+            state.last_failure = Some(format!(
+                "{}: {} ({})",
+                destination,
+                error,
+                Utc::now().format("%H:%M UTC")
+            ));
+        })?;
+        Ok(())
+    }
+
+    fn remove(&self, session_id: &str) -> Result<()> {
+        match fs::remove_file(self.path(session_id)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn path(&self, session_id: &str) -> PathBuf {
+        self.dir.join(file_stem(session_id))
+    }
+
+    /// 読み書きの競合を防ぐためのディレクトリ全体のロック（hookは短時間で終わるので1つで足りる）
+    fn lock(&self) -> Result<File> {
+        fs::create_dir_all(&self.dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join(".lock"))?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    /// ロック中に呼ぶ。読み手が書きかけの内容を見ないよう、一時ファイルに書いてから置き換える
+    fn save(&self, session_id: &str, state: &SessionState) -> Result<()> {
+        let path = self.path(session_id);
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec_pretty(state)?)?;
+        file.sync_data()?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+/// プレビューモードのペイロードの書き出し先（`<state_dir>/previews/<session_id>.log`）
+pub fn preview_file(session_id: &str) -> Result<PathBuf> {
+    Ok(config::state_dir()?
+        .join("previews")
+        .join(format!("{}.log", file_stem(session_id))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// 以前の形式のフラグファイルを書く
+    fn write_legacy(dir: &Path, session_id: &str, content: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(session_id), content).unwrap();
+    }
+
+    fn preview() -> SessionState {
+        SessionState {
+            preview: true,
+            ..Default::default()
+        }
+    }
+
+    fn store() -> (tempfile::TempDir, SessionStore) {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let store = SessionStore::at(temp_dir.path().join("sessions"));
+        (temp_dir, store)
+    }
+
+    #[test]
+    fn test_active_flag_lifecycle() {
+        // セッションIDごとの状態の作成・確認・削除をテスト
+        let (_temp_dir, store) = store();
+        let session_id = "test-session-lifecycle";
+        assert!(!store.is_active(session_id));
+
+        store
+            .activate(session_id, SessionState::default())
+            .expect("activate失敗");
+        assert!(store.is_active(session_id));
+        let state = store.load(session_id).unwrap().unwrap();
+        assert!(state.activated_at.is_some());
+        assert!(!state.preview);
+
+        store.deactivate(session_id).expect("deactivate失敗");
+        assert!(!store.is_active(session_id));
+    }
+
+    #[test]
+    fn test_next_sequence() {
+        let (_temp_dir, store) = store();
+        let session_id = "test-session-sequence";
+        store
+            .activate(session_id, SessionState::default())
+            .expect("activate失敗");
+        assert_eq!(
+            store.record(session_id, MessageKind::User).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            store.record(session_id, MessageKind::Assistant).unwrap(),
+            Some(2)
+        );
+        assert!(store.is_active(session_id));
+        let state = store.load(session_id).unwrap().unwrap();
+        assert_eq!((state.prompts, state.responses), (1, 1));
+        assert!(state.last_activity_at.is_some());
+
+        // 再度ONにすると番号は振り直し
+        store
+            .activate(session_id, SessionState::default())
+            .expect("activate失敗");
+        assert_eq!(
+            store.record(session_id, MessageKind::User).unwrap(),
+            Some(1)
+        );
+
+        // OFFのセッションは記録しない（状態を作り直さない）
+        store.deactivate(session_id).unwrap();
+        assert_eq!(store.record(session_id, MessageKind::User).unwrap(), None);
+        assert!(!store.is_active(session_id));
+    }
+
+    #[test]
+    fn test_record_failure() {
+        let (_temp_dir, store) = store();
+        let session_id = "test-session-failure";
+        // OFFのセッションには記録しない
+        store.record_failure(session_id, "team", "boom").unwrap();
+        assert!(!store.is_active(session_id));
+
+        store
+            .activate(session_id, SessionState::default())
+            .expect("activate失敗");
+        store.record_failure(session_id, "team", "first").unwrap();
+        store
+            .record_failure(session_id, "review", "second")
+            .unwrap();
+        let state = store.load(session_id).unwrap().unwrap();
+        assert_eq!(state.failures, 2);
+        assert!(
+            state
+                .last_failure
+                .as_deref()
+                .is_some_and(|f| f.starts_with("review: second (")),
+            "最後の失敗が記録されていない: {:?}",
+            state.last_failure
+        );
+    }
+
+    #[test]
+    fn test_private_turns() {
+        let (_temp_dir, store) = store();
+        let session_id = "test-session-private";
+        store
+            .activate(session_id, SessionState::default())
+            .expect("activate失敗");
+
+        // 接頭辞付きのプロンプトは、その応答まで送らない
+        assert_eq!(
+            store.start_turn(session_id, "!private a", true).unwrap(),
+            Some(true)
+        );
+        let state = store.load(session_id).unwrap().unwrap();
+        assert!(state.prompt_is_private("!private a"));
+        assert!(!state.prompt_is_private("other"));
+        assert_eq!(store.end_turn(session_id).unwrap(), Some(true));
+        // 同じターンの2回目のStopも送らない
+        assert_eq!(store.end_turn(session_id).unwrap(), Some(true));
+        assert_eq!(
+            store.start_turn(session_id, "b", false).unwrap(),
+            Some(false)
+        );
+        assert_eq!(store.end_turn(session_id).unwrap(), Some(false));
+
+        // skip は次の1回だけ。同期hookが記録する前でも非同期hookは送らないと判断できる
+        store
+            .update(session_id, |state| state.skip_next = true)
+            .unwrap();
+        assert!(store
+            .load(session_id)
+            .unwrap()
+            .unwrap()
+            .prompt_is_private("c"));
+        assert_eq!(
+            store.start_turn(session_id, "c", false).unwrap(),
+            Some(true)
+        );
+        assert!(store
+            .load(session_id)
+            .unwrap()
+            .unwrap()
+            .prompt_is_private("c"));
+        // 応答がないまま（中断されて）次のプロンプトが来ても、その記録で次のプロンプトを止めない
+        assert_eq!(
+            store.start_turn(session_id, "d", false).unwrap(),
+            Some(false)
+        );
+        assert!(!store
+            .load(session_id)
+            .unwrap()
+            .unwrap()
+            .prompt_is_private("d"));
+        // 中断されたターンが送らないものだったので、次の応答も念のため送らない
+        assert_eq!(store.end_turn(session_id).unwrap(), Some(true));
+        assert_eq!(
+            store.start_turn(session_id, "e", false).unwrap(),
+            Some(false)
+        );
+        assert_eq!(store.end_turn(session_id).unwrap(), Some(false));
+
+        store.deactivate(session_id).unwrap();
+        assert_eq!(store.start_turn(session_id, "f", true).unwrap(), None);
+    }
+
+    #[test]
+    fn test_stop_after_next_turn_started() {
+        let (_temp_dir, store) = store();
+        let session_id = "test-session-late-stop";
+        store
+            .activate(session_id, SessionState::default())
+            .expect("activate失敗");
+
+        // 送らないターンNのStopが、ターンN+1を始めた後に動く
+        store.start_turn(session_id, "!private n", true).unwrap();
+        store.start_turn(session_id, "n+1", false).unwrap();
+        assert_eq!(store.end_turn(session_id).unwrap(), Some(true));
+        assert_eq!(store.end_turn(session_id).unwrap(), Some(false));
+
+        // 送るターンNのStopが、送らないターンN+1を始めた後に動く
+        store.start_turn(session_id, "n+2", false).unwrap();
+        store.start_turn(session_id, "!private n+3", true).unwrap();
+        // どちらの応答かは区別できないので送らない
+        assert_eq!(store.end_turn(session_id).unwrap(), Some(true));
+        assert_eq!(store.end_turn(session_id).unwrap(), Some(true));
+
+        let state = store.load(session_id).unwrap().unwrap();
+        assert_eq!((state.turns, state.turns_ended), (4, 4));
+    }
+
+    #[test]
+    fn test_preview_flag_lifecycle() {
+        let (_temp_dir, store) = store();
+        let session_id = "test-session-preview";
+        store.activate(session_id, preview()).expect("activate失敗");
+        assert!(store.is_active(session_id), "プレビュー中も有効として扱う");
+        assert!(store.load(session_id).unwrap().unwrap().preview);
+
+        // /aloud-code:on で実際の送信に切り替わる
+        store
+            .activate(session_id, SessionState::default())
+            .expect("activate失敗");
+        assert!(
+            !store.load(session_id).unwrap().unwrap().preview,
+            "プレビューが解除されていない"
+        );
+    }
+
+    #[test]
+    fn test_activate_rejects_empty_session_id() {
+        let (_temp_dir, store) = store();
+        assert!(store.activate("", SessionState::default()).is_err());
+        assert!(!store.dir.join("unknown").exists());
+    }
+
+    #[test]
+    fn test_deactivate_idempotent() {
+        // 状態が存在しなくてもdeactivateはエラーにならない
+        let (_temp_dir, store) = store();
+        assert!(store.deactivate("nonexistent-session").is_ok());
+        assert!(store.deactivate("nonexistent-session").is_ok());
+    }
+
+    #[test]
+    fn test_multiple_sessions_concurrent() {
+        // 複数セッションが同時にONにできることを確認
+        let (_temp_dir, store) = store();
+        store
+            .activate("session-a", SessionState::default())
+            .expect("session-a activate失敗");
+        store
+            .activate("session-b", SessionState::default())
+            .expect("session-b activate失敗");
+
+        assert!(store.is_active("session-a"), "session-aがアクティブでない");
+        assert!(store.is_active("session-b"), "session-bがアクティブでない");
+        assert!(
+            !store.is_active("session-c"),
+            "session-cがアクティブになっている"
+        );
+        assert!(
+            !store.is_active(""),
+            "空のセッションIDがアクティブになっている"
+        );
+    }
+
+    #[test]
+    fn test_reads_legacy_flag_files() {
+        let (_temp_dir, store) = store();
+        write_legacy(&store.dir, "empty", "");
+        write_legacy(&store.dir, "counted", "41\n");
+
+        assert_eq!(store.load("empty").unwrap(), Some(SessionState::default()));
+        assert_eq!(
+            store.record("counted", MessageKind::User).unwrap(),
+            Some(42)
+        );
+        // 書き換えた後はJSONになる
+        let content = fs::read_to_string(store.dir.join("counted")).unwrap();
+        let state: SessionState = serde_json::from_str(&content).expect("JSONで保存されていない");
+        assert_eq!(state.last_sequence, 42);
+
+        // 後から増えた項目がなくても、知らない項目があっても読める
+        write_legacy(
+            &store.dir,
+            "old-fields",
+            r#"{"prompts": 2, "slack_thread_ts": "1700000000.000100", "removed": true}"#,
+        );
+        let state = store.load("old-fields").unwrap().unwrap();
+        assert_eq!(state.prompts, 2);
+        assert_eq!(state.slack_thread_ts.as_deref(), Some("1700000000.000100"));
+        assert!(!content.contains("slack_thread_ts"), "未設定の項目を書いた");
+
+        write_legacy(&store.dir, "broken", "{not json");
+        assert!(store.load("broken").is_err());
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let (_temp_dir, store) = store();
+        let now = Utc::now();
+        let limited = SessionState {
+            expires_at: Some(now + Duration::minutes(90)),
+            ..Default::default()
+        };
+        store.activate("pairing", limited).unwrap();
+        assert!(store.is_active("pairing"));
+        assert_eq!(store.end_if_time_is_up("pairing", now).unwrap(), None);
+
+        // 時間が過ぎたらOFFとして扱い、最初に気付いたときに状態を消す
+        store
+            .update("pairing", |s| {
+                s.expires_at = Some(now - Duration::seconds(1))
+            })
+            .unwrap();
+        assert!(!store.is_active("pairing"));
+        assert_eq!(store.record("pairing", MessageKind::User).unwrap(), None);
+        let ended = store.end_if_time_is_up("pairing", now).unwrap();
+        assert!(ended.is_some(), "終了した状態が返らない");
+        assert!(!store.dir.join("pairing").exists());
+        assert_eq!(store.end_if_time_is_up("pairing", now).unwrap(), None);
+    }
+
+    #[test]
+    fn test_expire_idle_session() {
+        let (_temp_dir, store) = store();
+        store.activate("idle", SessionState::default()).unwrap();
+        let state = store.load("idle").unwrap().unwrap();
+        let week = Some(Duration::days(7));
+        let now = Utc::now();
+
+        assert!(!store.expire("idle", &state, week, now).unwrap());
+        assert!(!store
+            .expire("idle", &state, None, now + Duration::days(365))
+            .unwrap());
+        assert!(store.is_active("idle"), "期限なしでOFFになった");
+
+        assert!(store
+            .expire("idle", &state, week, now + Duration::days(8))
+            .unwrap());
+        assert!(!store.is_active("idle"));
+    }
+
+    #[test]
+    fn test_prune_sessions() {
+        let (_temp_dir, store) = store();
+        let now = Utc::now();
+        store.activate("recent", SessionState::default()).unwrap();
+        store.activate("stale", SessionState::default()).unwrap();
+        store
+            .update("stale", |s| {
+                s.activated_at = Some(now - Duration::days(30));
+                s.last_activity_at = Some(now - Duration::days(10));
+            })
+            .unwrap();
+        // 以前の形式のフラグファイルは更新時刻で判断する
+        write_legacy(&store.dir, "legacy", "");
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(20 * 86400);
+        File::options()
+            .write(true)
+            .open(store.dir.join("legacy"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let expired = store.prune(Some(Duration::days(7)), now).unwrap();
+        let ids: Vec<&str> = expired.iter().map(|e| e.session_id.as_str()).collect();
+        assert_eq!(ids, vec!["legacy", "stale"], "最後の操作が古い順に返す");
+        assert!(store.is_active("recent"));
+        assert!(!store.is_active("stale"));
+        assert!(store.dir.join(".lock").exists(), "ロックファイルを消した");
+
+        assert!(store.prune(None, now).unwrap().is_empty());
+        assert!(SessionStore::at(store.dir.join("missing"))
+            .prune(Some(Duration::days(1)), now)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_records_are_not_lost() {
+        let (_temp_dir, store) = store();
+        store.activate("shared", SessionState::default()).unwrap();
+        let dir = store.dir.clone();
+        let handles: Vec<_> = (0..16)
+            .map(|_| {
+                let dir = dir.clone();
+                std::thread::spawn(move || {
+                    SessionStore::at(dir)
+                        .record("shared", MessageKind::User)
+                        .unwrap()
+                        .unwrap()
+                })
+            })
+            .collect();
+        let mut sequences: Vec<u64> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        sequences.sort();
+        assert_eq!(
+            sequences,
+            (1..=16).collect::<Vec<_>>(),
+            "通し番号が重複した"
+        );
+        assert_eq!(store.load("shared").unwrap().unwrap().prompts, 16);
+    }
+}
@@ -0,0 +1,442 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+use crate::event::EventEnvelope;
+use crate::hook::is_command;
+
+/// Claude Codeのセッションログ（`~/.claude/projects/<dir>/<session_id>.jsonl`）を読み取った結果
+#[derive(Debug, Default)]
+pub struct SessionLog {
+    pub session_id: String,
+    pub cwd: String,
+    pub model: Option<String>,
+    pub git_branch: Option<String>,
+    pub turns: Vec<Turn>,
+    /// 最初と最後の記録時刻
+    pub started_at: Option<DateTime<Utc>>,
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+/// ユーザーのプロンプト1つと、それに対するClaudeの最終応答
+#[derive(Debug, Clone, PartialEq)]
+pub struct Turn {
+    pub prompt: String,
+    pub prompt_at: DateTime<Utc>,
+    /// Stop hookの `last_assistant_message` に相当する、ターン最後のテキスト応答
+    pub response: Option<String>,
+    pub response_at: Option<DateTime<Utc>>,
+    /// 応答までの途中経過（テキストとツール呼び出し）を記録順に並べたもの
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Text(String),
+    Tool(ToolCall),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub input: Value,
+    /// ツールの実行結果（テキスト部分のみ）
+    pub result: Option<String>,
+    pub is_error: bool,
+}
+
+/// ログの1行（使うフィールドのみ）
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    #[serde(rename = "type")]
+    entry_type: String,
+    session_id: Option<String>,
+    cwd: Option<String>,
+    git_branch: Option<String>,
+    timestamp: Option<DateTime<Utc>>,
+    #[serde(default)]
+    is_sidechain: bool,
+    #[serde(default)]
+    is_meta: bool,
+    #[serde(default)]
+    is_compact_summary: bool,
+    message: Option<LogMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LogMessage {
+    id: Option<String>,
+    model: Option<String>,
+    #[serde(default)]
+    content: Value,
+}
+
+impl SessionLog {
+    /// JSONLの内容を解析する。読めない行は無視する
+    pub fn parse(content: &str) -> Self {
+        let mut log = SessionLog::default();
+        // 同じAPIメッセージのテキストは複数行に分かれて記録される
+        let mut response_id: Option<String> = None;
+
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            let Ok(entry) = serde_json::from_str::<Entry>(line) else {
+                continue;
+            };
+            if entry.is_sidechain {
+                continue;
+            }
+            if log.session_id.is_empty() {
+                if let Some(id) = &entry.session_id {
+                    log.session_id = id.clone();
+                }
+            }
+            if log.cwd.is_empty() {
+                if let Some(cwd) = &entry.cwd {
+                    log.cwd = cwd.clone();
+                }
+            }
+            if entry.git_branch.as_deref().is_some_and(|b| !b.is_empty()) {
+                log.git_branch = entry.git_branch.clone();
+            }
+            let (Some(message), Some(timestamp)) = (entry.message, entry.timestamp) else {
+                continue;
+            };
+            log.started_at.get_or_insert(timestamp);
+            log.ended_at = Some(timestamp);
+
+            match entry.entry_type.as_str() {
+                "user" if !entry.is_meta && !entry.is_compact_summary => {
+                    if let Some(turn) = log.turns.last_mut() {
+                        attach_tool_results(turn, &message.content);
+                    }
+                    let Some(prompt) = prompt_text(&message.content) else {
+                        continue;
+                    };
+                    response_id = None;
+                    log.turns.push(Turn {
+                        prompt,
+                        prompt_at: timestamp,
+                        response: None,
+                        response_at: None,
+                        steps: Vec::new(),
+                    });
+                }
+                "assistant" => {
+                    if let Some(model) = message.model.filter(|m| !m.starts_with('<')) {
+                        log.model = Some(model);
+                    }
+                    let text = block_texts(&message.content);
+                    let Some(turn) = log.turns.last_mut() else {
+                        continue;
+                    };
+                    turn.steps.extend(steps(&message.content));
+                    if text.is_empty() {
+                        continue;
+                    }
+                    match (&response_id, &message.id, &mut turn.response) {
+                        (Some(current), Some(id), Some(response)) if current == id => {
+                            response.push_str("\n\n");
+                            response.push_str(&text);
+                        }
+                        _ => turn.response = Some(text),
+                    }
+                    turn.response_at = Some(timestamp);
+                    response_id = message.id;
+                }
+                _ => {}
+            }
+        }
+        log
+    }
+
+    /// ローカルアーカイブのイベントから組み立てる（ツール呼び出しは記録されていない）
+    pub fn from_envelopes(events: &[EventEnvelope]) -> Self {
+        let mut log = SessionLog::default();
+        for event in events {
+            let Ok(timestamp) = event.timestamp.parse::<DateTime<Utc>>() else {
+                continue;
+            };
+            log.session_id.clone_from(&event.session_id);
+            log.cwd.clone_from(&event.cwd);
+            if event.model.is_some() {
+                log.model.clone_from(&event.model);
+            }
+            log.started_at.get_or_insert(timestamp);
+            log.ended_at = Some(timestamp);
+            let text = event.content.text.clone();
+            match event.event_type.as_str() {
+                "prompt" => log.turns.push(Turn {
+                    prompt: text,
+                    prompt_at: timestamp,
+                    response: None,
+                    response_at: None,
+                    steps: Vec::new(),
+                }),
+                "response" => {
+                    if let Some(turn) = log.turns.last_mut() {
+                        turn.steps.push(Step::Text(text.clone()));
+                        turn.response = Some(text);
+                        turn.response_at = Some(timestamp);
+                    }
+                }
+                _ => {}
+            }
+        }
+        log
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("{} を読み込めません: {}", path.display(), e))?;
+        let log = Self::parse(&content);
+        if log.session_id.is_empty() {
+            anyhow::bail!(
+                "Claude Codeのセッションログとして読めません: {}",
+                path.display()
+            );
+        }
+        Ok(log)
+    }
+}
+
+/// 実際にユーザーが入力したプロンプトのテキスト（ツール結果やスラッシュコマンドの出力は除く）
+fn prompt_text(content: &Value) -> Option<String> {
+    let text = match content {
+        Value::String(s) => s.clone(),
+        Value::Array(blocks) => {
+            if blocks.iter().any(|b| b["type"] == "tool_result") {
+                return None;
+            }
+            block_texts(content)
+        }
+        _ => return None,
+    };
+    let trimmed = text.trim();
+    // スラッシュコマンドやbashモードはタグで包まれて記録される
+    let wrapped = ["<command-", "<local-command-", "<bash-"]
+        .iter()
+        .any(|tag| trimmed.starts_with(tag));
+    if trimmed.is_empty() || wrapped || is_command(trimmed) {
+        return None;
+    }
+    Some(text)
+}
+
+/// assistantメッセージの text / tool_use ブロック
+fn steps(content: &Value) -> Vec<Step> {
+    let Value::Array(blocks) = content else {
+        return Vec::new();
+    };
+    blocks
+        .iter()
+        .filter_map(|block| match block["type"].as_str()? {
+            "text" => Some(Step::Text(block["text"].as_str()?.to_string())),
+            "tool_use" => Some(Step::Tool(ToolCall {
+                id: block["id"].as_str().unwrap_or_default().to_string(),
+                name: block["name"].as_str().unwrap_or_default().to_string(),
+                input: block["input"].clone(),
+                result: None,
+                is_error: false,
+            })),
+            _ => None,
+        })
+        .collect()
+}
+
+/// userメッセージの tool_result を対応するツール呼び出しに結びつける
+fn attach_tool_results(turn: &mut Turn, content: &Value) {
+    let Value::Array(blocks) = content else {
+        return;
+    };
+    for block in blocks.iter().filter(|b| b["type"] == "tool_result") {
+        let id = block["tool_use_id"].as_str().unwrap_or_default();
+        let call = turn.steps.iter_mut().find_map(|step| match step {
+            Step::Tool(call) if call.id == id => Some(call),
+            _ => None,
+        });
+        if let Some(call) = call {
+            call.result = Some(block_texts(&block["content"]));
+            call.is_error = block["is_error"].as_bool().unwrap_or(false);
+        }
+    }
+}
+
+/// `content` 配列の text ブロックを連結する
+fn block_texts(content: &Value) -> String {
+    match content {
+        Value::String(s) => s.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter(|b| b["type"] == "text")
+            .filter_map(|b| b["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        _ => String::new(),
+    }
+}
+
+/// Claude Codeの設定ディレクトリ（`CLAUDE_CONFIG_DIR` または `~/.claude`）
+fn claude_dir() -> Result<PathBuf> {
+    if let Ok(dir) = std::env::var("CLAUDE_CONFIG_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    dirs::home_dir()
+        .map(|home| home.join(".claude"))
+        .ok_or_else(|| anyhow::anyhow!("ホームディレクトリが見つかりません"))
+}
+
+/// ファイルパス、またはセッションIDからセッションログのパスを求める
+pub fn locate(arg: &str) -> Result<PathBuf> {
+    let path = Path::new(arg);
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    if arg.ends_with(".jsonl") || arg.contains('/') {
+        anyhow::bail!("ファイルがありません: {}", arg);
+    }
+    let projects = claude_dir()?.join("projects");
+    let entries = std::fs::read_dir(&projects)
+        .map_err(|e| anyhow::anyhow!("{} を読み込めません: {}", projects.display(), e))?;
+    for entry in entries.flatten() {
+        let candidate = entry.path().join(format!("{}.jsonl", arg));
+        if candidate.is_file() {
+            return Ok(candidate);
+        }
+    }
+    anyhow::bail!(
+        "セッション {} のログが {} にありません",
+        arg,
+        projects.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn line(value: Value) -> String {
+        value.to_string()
+    }
+
+    fn user(text: Value, at: &str) -> String {
+        line(json!({
+            "type": "user", "sessionId": "s1", "cwd": "/home/user/my-project",
+            "gitBranch": "main", "timestamp": at,
+            "message": {"role": "user", "content": text}
+        }))
+    }
+
+    fn assistant(id: &str, content: Value, at: &str) -> String {
+        line(json!({
+            "type": "assistant", "sessionId": "s1", "timestamp": at,
+            "message": {"id": id, "role": "assistant", "model": "claude-sonnet-4-6", "content": content}
+        }))
+    }
+
+    fn sample() -> String {
+        [
+            line(json!({"type": "summary", "summary": "x"})),
+            user(json!("Fix the flaky test"), "2026-01-02T03:00:00Z"),
+            assistant(
+                "m1",
+                json!([{"type": "text", "text": "Looking."}, {"type": "tool_use", "id": "t", "name": "Read", "input": {}}]),
+                "2026-01-02T03:00:01Z",
+            ),
+            user(
+                json!([{"type": "tool_result", "tool_use_id": "t", "content": "file"}]),
+                "2026-01-02T03:00:02Z",
+            ),
+            assistant(
+                "m2",
+                json!([{"type": "text", "text": "Fixed it."}]),
+                "2026-01-02T03:00:03Z",
+            ),
+            assistant(
+                "m2",
+                json!([{"type": "text", "text": "Run the tests."}]),
+                "2026-01-02T03:00:04Z",
+            ),
+            user(
+                json!("<command-name>/clear</command-name>"),
+                "2026-01-02T03:01:00Z",
+            ),
+            user(json!("/aloud-code:on"), "2026-01-02T03:01:30Z"),
+            line(json!({
+                "type": "user", "isMeta": true, "timestamp": "2026-01-02T03:01:40Z",
+                "message": {"role": "user", "content": "Caveat: meta"}
+            })),
+            line(json!({
+                "type": "user", "isSidechain": true, "timestamp": "2026-01-02T03:01:45Z",
+                "message": {"role": "user", "content": "subagent prompt"}
+            })),
+            user(
+                json!([{"type": "text", "text": "Thanks"}]),
+                "2026-01-02T03:02:00Z",
+            ),
+            "not json".to_string(),
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn test_parse_turns() {
+        let log = SessionLog::parse(&sample());
+        assert_eq!(log.session_id, "s1");
+        assert_eq!(log.cwd, "/home/user/my-project");
+        assert_eq!(log.model.as_deref(), Some("claude-sonnet-4-6"));
+        assert_eq!(log.git_branch.as_deref(), Some("main"));
+        assert_eq!(log.turns.len(), 2, "{:?}", log.turns);
+
+        let first = &log.turns[0];
+        assert_eq!(first.prompt, "Fix the flaky test");
+        // ターン最後のAPIメッセージのテキストだけが最終応答になる
+        assert_eq!(
+            first.response.as_deref(),
+            Some("Fixed it.\n\nRun the tests.")
+        );
+        assert_eq!(
+            first.response_at,
+            Some("2026-01-02T03:00:04Z".parse().unwrap())
+        );
+
+        assert_eq!(
+            log.started_at,
+            Some("2026-01-02T03:00:00Z".parse().unwrap())
+        );
+        assert_eq!(log.ended_at, Some("2026-01-02T03:02:00Z".parse().unwrap()));
+        assert_eq!(first.steps.len(), 4, "{:?}", first.steps);
+        assert_eq!(first.steps[0], Step::Text("Looking.".to_string()));
+        match &first.steps[1] {
+            Step::Tool(call) => {
+                assert_eq!(call.name, "Read");
+                assert_eq!(call.result.as_deref(), Some("file"));
+                assert!(!call.is_error);
+            }
+            other => panic!("ツール呼び出しではない: {:?}", other),
+        }
+
+        let second = &log.turns[1];
+        assert_eq!(second.prompt, "Thanks");
+        assert_eq!(second.response, None);
+    }
+
+    #[test]
+    fn test_read_rejects_other_files() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "{\"foo\": 1}\n").unwrap();
+        assert!(SessionLog::read(file.path()).is_err());
+    }
+
+    #[test]
+    fn test_locate_by_path() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_string_lossy().into_owned();
+        assert_eq!(locate(&path).unwrap(), file.path());
+        assert!(locate("/nonexistent/x.jsonl").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// 署名を入れるヘッダー（値は `sha256=<hex>`）
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+/// 署名時刻（UNIX秒）を入れるヘッダー
+pub const TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// `<timestamp>.<body>` をHMAC-SHA256で署名し、`sha256=<hex>` 形式で返す
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    format!(
+        "sha256={}",
+        hex::encode(mac(secret, timestamp, body).finalize().into_bytes())
+    )
+}
+
+/// 受信側での検証手順（署名の一致と、時刻が `tolerance` 以内であること）。送信側では使わず、`sign` のテストに使う
+#[cfg(test)]
+pub fn verify(
+    secret: &str,
+    timestamp: &str,
+    body: &[u8],
+    signature: &str,
+    now: i64,
+    tolerance: std::time::Duration,
+) -> anyhow::Result<()> {
+    let timestamp: i64 = timestamp
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("タイムスタンプが不正です: {}", timestamp))?;
+    if now.abs_diff(timestamp) > tolerance.as_secs() {
+        anyhow::bail!("タイムスタンプが許容範囲外です: {}", timestamp);
+    }
+    let expected = signature
+        .strip_prefix("sha256=")
+        .and_then(|hex| hex::decode(hex).ok())
+        .ok_or_else(|| anyhow::anyhow!("署名の形式が不正です: {}", signature))?;
+    // 比較は定数時間で行う
+    mac(secret, timestamp, body)
+        .verify_slice(&expected)
+        .map_err(|_| anyhow::anyhow!("署名が一致しません"))
+}
+
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMACは任意長の鍵を受け付ける");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const TOLERANCE: Duration = Duration::from_secs(300);
+
+    #[test]
+    fn test_sign_known_vector() {
+        // echo -n '1700000000.{"text":"hi"}' | openssl dgst -sha256 -hmac s3cret
+        assert_eq!(
+            sign("s3cret", 1_700_000_000, br#"{"text":"hi"}"#),
+            "sha256=a4abab2c9ec335a751cf8c3848e413a84a4e0eef17a9660d993911fafacadb66"
+        );
+    }
+
+    #[test]
+    fn test_verify_roundtrip() {
+        let body = br#"{"text":"hi"}"#;
+        let signature = sign("s3cret", 1_700_000_000, body);
+        assert!(verify(
+            "s3cret",
+            "1700000000",
+            body,
+            &signature,
+            1_700_000_010,
+            TOLERANCE
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_verify_rejects_tampering() {
+        let body = br#"{"text":"hi"}"#;
+        let signature = sign("s3cret", 1_700_000_000, body);
+        let now = 1_700_000_000;
+
+        assert!(verify("other", "1700000000", body, &signature, now, TOLERANCE).is_err());
+        assert!(
+            verify(
+                "s3cret",
+                "1700000000",
+                br#"{"text":"ho"}"#,
+                &signature,
+                now,
+                TOLERANCE
+            )
+            .is_err(),
+            "ボディの改ざんを検出できない"
+        );
+        assert!(
+            verify("s3cret", "1700000001", body, &signature, now, TOLERANCE).is_err(),
+            "タイムスタンプの改ざんを検出できない"
+        );
+        assert!(verify("s3cret", "1700000000", body, "sha256=zz", now, TOLERANCE).is_err());
+        assert!(verify("s3cret", "1700000000", body, "md5=00", now, TOLERANCE).is_err());
+    }
+
+    #[test]
+    fn test_verify_rejects_stale_timestamp() {
+        let body = b"{}";
+        let signature = sign("s3cret", 1_700_000_000, body);
+        assert!(
+            verify(
+                "s3cret",
+                "1700000000",
+                body,
+                &signature,
+                1_700_000_301,
+                TOLERANCE
+            )
+            .is_err(),
+            "古いリクエストの再送を拒否できない"
+        );
+        assert!(verify("s3cret", "now", body, &signature, 1_700_000_000, TOLERANCE).is_err());
+    }
+}
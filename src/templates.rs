@@ -0,0 +1,396 @@
+use anyhow::Result;
+use chrono::SecondsFormat;
+use minijinja::{AutoEscape, Environment};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::config::TemplateConfig;
+use crate::formatter::{self, Message, MessageKind};
+use crate::git::GitInfo;
+
+/// 組み込みテンプレート（名前, ユーザー用, アシスタント用）
+const BUILTIN_TEMPLATES: &[(&str, &str, &str)] = &[
+    (
+        "slack",
+        include_str!("templates/slack_user.json.j2"),
+        include_str!("templates/slack_assistant.json.j2"),
+    ),
+    (
+        "discord",
+        include_str!("templates/discord_user.json.j2"),
+        include_str!("templates/discord_assistant.json.j2"),
+    ),
+    (
+        "text",
+        include_str!("templates/text_user.json.j2"),
+        include_str!("templates/text_assistant.json.j2"),
+    ),
+];
+
+/// テンプレートに渡す値（READMEの「Payload templates」に一覧がある）
+#[derive(Debug, Serialize)]
+pub struct TemplateContext<'a> {
+    /// `user-prompt` または `stop`
+    pub event: &'static str,
+    /// ユーザーのプロンプト（`user-prompt` のときのみ）
+    pub prompt: Option<&'a str>,
+    /// Claudeの応答（`stop` のときのみ）
+    pub message: Option<&'a str>,
+    /// `prompt` または `message`
+    pub text: &'a str,
+    pub session_id: &'a str,
+    pub short_id: &'a str,
+    pub project: &'a str,
+    pub cwd: &'a str,
+    /// `project [short_id]`（タイトルがあれば `title · project [short_id]`）
+    pub username: String,
+    pub model: Option<&'a str>,
+    pub labels: &'a [String],
+    pub title: Option<&'a str>,
+    pub tags: &'a [String],
+    /// RFC 3339形式（UTC）
+    pub timestamp: String,
+    pub timestamp_unix: i64,
+    pub git: Option<GitInfo>,
+}
+
+impl<'a> TemplateContext<'a> {
+    pub fn new(message: &Message<'a>) -> Self {
+        let Message {
+            kind,
+            text,
+            ctx,
+            timestamp: now,
+            ..
+        } = *message;
+        TemplateContext {
+            event: kind.event_name(),
+            prompt: (kind == MessageKind::User).then_some(text),
+            message: (kind == MessageKind::Assistant).then_some(text),
+            text,
+            session_id: &ctx.session_id,
+            short_id: ctx.short_id(),
+            project: ctx.project_name(),
+            cwd: &ctx.cwd,
+            username: ctx.username(),
+            model: ctx.model.as_deref(),
+            labels: &ctx.labels,
+            title: ctx.title.as_deref(),
+            tags: &ctx.tags,
+            timestamp: now.to_rfc3339_opts(SecondsFormat::Secs, true),
+            timestamp_unix: now.timestamp(),
+            git: message.git.clone(),
+        }
+    }
+}
+
+/// 組み込みテンプレートと設定ファイルのテンプレートをコンパイルしたもの
+pub struct Templates {
+    env: Environment<'static>,
+}
+
+impl Templates {
+    pub fn new(user_templates: &BTreeMap<String, TemplateConfig>) -> Result<Self> {
+        let mut env = Environment::new();
+        // 出力はJSONなのでHTMLエスケープはしない（文字列は `tojson` で埋め込む）
+        env.set_auto_escape_callback(|_| AutoEscape::None);
+        env.add_filter("truncate", |value: String, max_len: usize| {
+            formatter::truncate(&value, max_len)
+        });
+
+        for (name, user, assistant) in BUILTIN_TEMPLATES {
+            env.add_template_owned(template_key(name, MessageKind::User), *user)?;
+            env.add_template_owned(template_key(name, MessageKind::Assistant), *assistant)?;
+        }
+        for (name, template) in user_templates {
+            if is_builtin(name) {
+                anyhow::bail!(
+                    "[templates.{}] 組み込みテンプレートと同じ名前は使えません",
+                    name
+                );
+            }
+            env.add_template_owned(template_key(name, MessageKind::User), template.user.clone())
+                .map_err(|e| anyhow::anyhow!("[templates.{}] user: {}", name, describe(&e)))?;
+            env.add_template_owned(
+                template_key(name, MessageKind::Assistant),
+                template.assistant.clone(),
+            )
+            .map_err(|e| anyhow::anyhow!("[templates.{}] assistant: {}", name, describe(&e)))?;
+        }
+        Ok(Templates { env })
+    }
+
+    /// テンプレート名が組み込み・設定ファイルのどちらかに存在するか
+    pub fn contains(&self, name: &str) -> bool {
+        self.env
+            .get_template(&template_key(name, MessageKind::User))
+            .is_ok()
+    }
+
+    /// テンプレートを描画し、JSONとしてパースしたペイロードを返す
+    pub fn render(&self, name: &str, kind: MessageKind, ctx: &TemplateContext) -> Result<Value> {
+        let template = self
+            .env
+            .get_template(&template_key(name, kind))
+            .map_err(|_| anyhow::anyhow!("テンプレート {} が見つかりません", name))?;
+        let rendered = template
+            .render(ctx)
+            .map_err(|e| anyhow::anyhow!("テンプレート {} の描画に失敗: {}", name, describe(&e)))?;
+        serde_json::from_str(&rendered).map_err(|e| {
+            anyhow::anyhow!(
+                "テンプレート {} の出力がJSONではありません ({}): {}",
+                name,
+                e,
+                rendered
+            )
+        })
+    }
+}
+
+fn is_builtin(name: &str) -> bool {
+    BUILTIN_TEMPLATES.iter().any(|(n, _, _)| *n == name)
+}
+
+fn template_key(name: &str, kind: MessageKind) -> String {
+    match kind {
+        MessageKind::User => format!("{}/user", name),
+        MessageKind::Assistant => format!("{}/assistant", name),
+    }
+}
+
+/// minijinjaのエラーを行番号付きの1行に整える
+fn describe(err: &minijinja::Error) -> String {
+    match err.line() {
+        Some(line) => format!(
+            "line {}: {}",
+            line,
+            err.detail().unwrap_or(&err.to_string())
+        ),
+        None => err.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatter::SessionContext;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn test_ctx(labels: &[&str]) -> SessionContext {
+        SessionContext {
+            session_id: "abcdef1234567890".to_string(),
+            cwd: "/home/user/my-project".to_string(),
+            model: Some("claude-sonnet-4-6".to_string()),
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn fixed_now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap()
+    }
+
+    fn git() -> Option<GitInfo> {
+        Some(GitInfo {
+            branch: Some("main".to_string()),
+            commit: Some("1a2b3c4".to_string()),
+            repository: Some("my-project".to_string()),
+        })
+    }
+
+    fn message<'a>(
+        kind: MessageKind,
+        text: &'a str,
+        ctx: &'a SessionContext,
+        git: Option<GitInfo>,
+    ) -> Message<'a> {
+        Message {
+            kind,
+            text,
+            ctx,
+            timestamp: fixed_now(),
+            sequence: 1,
+            git,
+        }
+    }
+
+    fn render_builtin(name: &str, kind: MessageKind, text: &str, ctx: &SessionContext) -> Value {
+        let templates = Templates::new(&BTreeMap::new()).expect("テンプレートのコンパイル失敗");
+        let tctx = TemplateContext::new(&message(kind, text, ctx, git()));
+        templates.render(name, kind, &tctx).expect("描画失敗")
+    }
+
+    fn golden(file: &str) -> Value {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(file);
+        let content = std::fs::read_to_string(&path)
+            .unwrap_or_else(|_| panic!("goldenファイルがない: {}", path.display()));
+        serde_json::from_str(&content).expect("goldenファイルのパース失敗")
+    }
+
+    #[test]
+    fn test_builtin_templates_match_golden_files() {
+        let ctx = SessionContext {
+            tags: vec!["auth".to_string()],
+            ..test_ctx(&["backend"])
+        };
+        for (name, _, _) in BUILTIN_TEMPLATES {
+            for (kind, suffix, text) in [
+                (
+                    MessageKind::User,
+                    "user",
+                    "Fix the \"flaky\" test\nin auth.rs",
+                ),
+                (
+                    MessageKind::Assistant,
+                    "assistant",
+                    "Done! See `auth.rs` <3",
+                ),
+            ] {
+                let rendered = render_builtin(name, kind, text, &ctx);
+                let expected = golden(&format!("{}_{}.json", name, suffix));
+                assert_eq!(
+                    rendered, expected,
+                    "{} ({}) がgoldenと一致しない",
+                    name, suffix
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_slack_template_matches_default_formatter() {
+        // 組み込みの slack テンプレートはテンプレート未指定時の出力と同じ
+        for (labels, tags) in [
+            (&[][..], &[][..]),
+            (&["backend", "oncall"][..], &[][..]),
+            (&[][..], &["auth"][..]),
+            (&["backend"][..], &["auth", "flaky"][..]),
+        ] {
+            let ctx = SessionContext {
+                tags: tags.iter().map(|t| t.to_string()).collect(),
+                ..test_ctx(labels)
+            };
+            let long_text = "a".repeat(4000);
+            for text in ["hello", long_text.as_str()] {
+                for kind in [MessageKind::User, MessageKind::Assistant] {
+                    assert_eq!(
+                        render_builtin("slack", kind, text, &ctx),
+                        formatter::format_kind(kind, text, &ctx)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncate_filter_with_small_limit() {
+        let mut user_templates = BTreeMap::new();
+        user_templates.insert(
+            "tiny".to_string(),
+            TemplateConfig {
+                user: r#"{"text": {{ prompt | truncate(2) | tojson }}}"#.to_string(),
+                assistant: r#"{"text": {{ message | truncate(0) | tojson }}}"#.to_string(),
+            },
+        );
+        let templates = Templates::new(&user_templates).expect("コンパイル失敗");
+        let ctx = test_ctx(&[]);
+        for kind in [MessageKind::User, MessageKind::Assistant] {
+            let tctx = TemplateContext::new(&message(kind, "hello", &ctx, None));
+            let value = templates.render("tiny", kind, &tctx).unwrap();
+            assert_eq!(value["text"], "...");
+        }
+    }
+
+    #[test]
+    fn test_user_template_context() {
+        let mut user_templates = BTreeMap::new();
+        user_templates.insert(
+            "mine".to_string(),
+            TemplateConfig {
+                user: r#"{"who": "user", "prompt": {{ prompt | tojson }}, "branch": {{ git.branch | tojson }}, "at": {{ timestamp | tojson }}, "model": {{ model | tojson }}}"#.to_string(),
+                assistant: r#"{"who": "claude", "prompt": {{ prompt | tojson }}, "message": {{ message | tojson }}}"#.to_string(),
+            },
+        );
+        let templates = Templates::new(&user_templates).expect("コンパイル失敗");
+        assert!(templates.contains("mine"));
+        assert!(templates.contains("slack"));
+        assert!(!templates.contains("missing"));
+
+        let ctx = test_ctx(&[]);
+        let tctx = TemplateContext::new(&message(MessageKind::User, "hi", &ctx, git()));
+        let value = templates.render("mine", MessageKind::User, &tctx).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "who": "user",
+                "prompt": "hi",
+                "branch": "main",
+                "at": "2026-01-02T03:04:05Z",
+                "model": "claude-sonnet-4-6"
+            })
+        );
+
+        let tctx = TemplateContext::new(&message(MessageKind::Assistant, "ok", &ctx, None));
+        let value = templates
+            .render("mine", MessageKind::Assistant, &tctx)
+            .unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"who": "claude", "prompt": null, "message": "ok"})
+        );
+    }
+
+    #[test]
+    fn test_compile_error_has_location() {
+        let mut user_templates = BTreeMap::new();
+        user_templates.insert(
+            "broken".to_string(),
+            TemplateConfig {
+                user: "{\"text\": {{ prompt | tojson }}}".to_string(),
+                assistant: "{\n\"text\": {{ message | tojson }\n}".to_string(),
+            },
+        );
+        let err = Templates::new(&user_templates)
+            .err()
+            .expect("エラーにならない");
+        let message = err.to_string();
+        assert!(
+            message.contains("[templates.broken] assistant"),
+            "{}",
+            message
+        );
+        assert!(message.contains("line 2"), "{}", message);
+    }
+
+    #[test]
+    fn test_render_non_json_is_error() {
+        let mut user_templates = BTreeMap::new();
+        user_templates.insert(
+            "plain".to_string(),
+            TemplateConfig {
+                user: "hello {{ prompt }}".to_string(),
+                assistant: "{}".to_string(),
+            },
+        );
+        let templates = Templates::new(&user_templates).unwrap();
+        let ctx = test_ctx(&[]);
+        let tctx = TemplateContext::new(&message(MessageKind::User, "hi", &ctx, None));
+        assert!(templates.render("plain", MessageKind::User, &tctx).is_err());
+    }
+
+    #[test]
+    fn test_builtin_name_cannot_be_redefined() {
+        let mut user_templates = BTreeMap::new();
+        user_templates.insert(
+            "slack".to_string(),
+            TemplateConfig {
+                user: "{}".to_string(),
+                assistant: "{}".to_string(),
+            },
+        );
+        assert!(Templates::new(&user_templates).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::Result;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::archive::file_stem;
+use crate::formatter::{Message, MessageKind};
+
+/// 見出しなどに使う時刻の形式（UTC）
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
+
+/// セッションのMarkdownファイルに1メッセージ分の節を追記し、そのパスを返す
+pub fn append(dir: &Path, message: &Message) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(file_name(message));
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    // 並行して動くhookプロセスの節が混ざらないよう、ヘッダーの判定から書き込みまでロックする
+    file.lock()?;
+    let mut content = String::new();
+    if file.metadata()?.len() == 0 {
+        content.push_str(&header(message));
+    }
+    content.push_str(&section(message));
+    file.write_all(content.as_bytes())?;
+    file.sync_data()?;
+    Ok(path)
+}
+
+/// `<プロジェクト名>-<セッションID>.md`
+fn file_name(message: &Message) -> String {
+    format!(
+        "{}-{}.md",
+        file_stem(message.ctx.project_name()),
+        file_stem(&message.ctx.session_id)
+    )
+}
+
+/// ファイル作成時に一度だけ書くプロジェクト情報
+fn header(message: &Message) -> String {
+    let ctx = message.ctx;
+    let title = match &ctx.title {
+        Some(title) => format!("{} · {}", title, ctx.project_name()),
+        None => format!("{} · session {}", ctx.project_name(), ctx.short_id()),
+    };
+    let mut header = format!(
+        "# {}\n\n- Session: `{}`\n- Directory: `{}`\n",
+        title, ctx.session_id, ctx.cwd
+    );
+    if let Some(model) = &ctx.model {
+        header.push_str(&format!("- Model: {}\n", model));
+    }
+    if !ctx.labels.is_empty() {
+        header.push_str(&format!("- Labels: {}\n", ctx.labels.join(", ")));
+    }
+    if !ctx.tags.is_empty() {
+        let tags: Vec<String> = ctx.tags.iter().map(|t| format!("#{}", t)).collect();
+        header.push_str(&format!("- Tags: {}\n", tags.join(" ")));
+    }
+    header.push_str(&format!(
+        "- Started: {}\n",
+        message.timestamp.format(TIME_FORMAT)
+    ));
+    header
+}
+
+fn section(message: &Message) -> String {
+    let heading = match message.kind {
+        MessageKind::User => "👤 User",
+        MessageKind::Assistant => "🤖 Claude",
+    };
+    format!(
+        "\n## {}\n\n_{}_\n\n{}\n",
+        heading,
+        message.timestamp.format(TIME_FORMAT),
+        message.text.trim_end()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatter::SessionContext;
+    use chrono::{TimeZone, Utc};
+
+    fn ctx() -> SessionContext {
+        SessionContext {
+            session_id: "abcdef12-3456-7890-abcd-ef1234567890".to_string(),
+            cwd: "/home/user/my-project".to_string(),
+            model: Some("claude-sonnet-4-6".to_string()),
+            labels: vec!["backend".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn message<'a>(ctx: &'a SessionContext, kind: MessageKind, text: &'a str) -> Message<'a> {
+        Message {
+            kind,
+            text,
+            ctx,
+            timestamp: Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap(),
+            sequence: 1,
+            git: None,
+        }
+    }
+
+    #[test]
+    fn test_transcript_reads_like_a_document() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let ctx = ctx();
+        append(
+            temp_dir.path(),
+            &message(&ctx, MessageKind::User, "Fix the flaky test"),
+        )
+        .unwrap();
+        let path = append(
+            temp_dir.path(),
+            &message(
+                &ctx,
+                MessageKind::Assistant,
+                "Done.\n\n```rust\nfn a() {}\n```\n",
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            path,
+            temp_dir
+                .path()
+                .join("my-project-abcdef12-3456-7890-abcd-ef1234567890.md")
+        );
+
+        let expected = "\
+# my-project · session abcdef12
+
+- Session: `abcdef12-3456-7890-abcd-ef1234567890`
+- Directory: `/home/user/my-project`
+- Model: claude-sonnet-4-6
+- Labels: backend
+- Started: 2026-01-02 03:04:05 UTC
+
+## 👤 User
+
+_2026-01-02 03:04:05 UTC_
+
+Fix the flaky test
+
+## 🤖 Claude
+
+_2026-01-02 03:04:05 UTC_
+
+Done.
+
+```rust
+fn a() {}
+```
+";
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_header_with_title_and_tags() {
+        let ctx = SessionContext {
+            title: Some("Fix flaky auth tests".to_string()),
+            tags: vec!["backend".to_string(), "auth".to_string()],
+            ..ctx()
+        };
+        let header = header(&message(&ctx, MessageKind::User, "hi"));
+        assert!(
+            header.starts_with("# Fix flaky auth tests · my-project\n"),
+            "{}",
+            header
+        );
+        assert!(header.contains("- Tags: #backend #auth\n"), "{}", header);
+    }
+
+    #[test]
+    fn test_concurrent_appends_write_header_once() {
+        let temp_dir = tempfile::TempDir::new().expect("一時ディレクトリ作成失敗");
+        let dir = temp_dir.path().to_path_buf();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let dir = dir.clone();
+                std::thread::spawn(move || {
+                    let ctx = ctx();
+                    append(&dir, &message(&ctx, MessageKind::User, "hi")).unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let path = dir.join("my-project-abcdef12-3456-7890-abcd-ef1234567890.md");
+        let content = fs::read_to_string(path).unwrap();
+        assert_eq!(content.matches("# my-project").count(), 1);
+        assert_eq!(content.matches("## 👤 User").count(), 8);
+    }
+}
@@ -1,27 +1,199 @@
 use anyhow::Result;
 use serde_json::Value;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::signature;
+
+/// 1回の送信結果（`aloud-code test` での表示用）
+#[derive(Debug)]
+pub struct SendReport {
+    pub status: reqwest::StatusCode,
+    pub latency: Duration,
+    pub body: String,
+}
+
+/// 送信するJSONボディと、そのリクエストに付けるヘッダー
+#[derive(Debug, Clone)]
+pub struct Payload {
+    pub body: Value,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Payload {
+    /// ヘッダーを1つ追加する（`content-type` を指定するとデフォルトの `application/json` を置き換える）
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    fn content_type(&self) -> &str {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .map(|(_, value)| value.as_str())
+            .unwrap_or("application/json")
+    }
+}
+
+impl From<Value> for Payload {
+    fn from(body: Value) -> Self {
+        Payload {
+            body,
+            headers: Vec::new(),
+        }
+    }
+}
+
+/// HTTPクライアントの接続設定（プロキシ・証明書）
+#[derive(Debug, Default)]
+pub struct ClientOptions {
+    /// 明示的なプロキシ。None なら HTTPS_PROXY などの環境変数に従う
+    pub proxy: Option<String>,
+    /// 追加で信頼するルート証明書（PEM、複数の証明書を含んでよい）
+    pub ca_certs: Vec<PathBuf>,
+    /// mTLSのクライアント証明書と秘密鍵（None なら証明書ファイルに鍵も含まれる）
+    pub client_identity: Option<(PathBuf, Option<PathBuf>)>,
+}
+
+impl ClientOptions {
+    fn build_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder().timeout(Duration::from_secs(10));
+        if let Some(proxy) = &self.proxy {
+            // 明示したプロキシでも NO_PROXY は尊重する
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|e| anyhow::anyhow!("プロキシURLが不正です: {}", e))?
+                .no_proxy(reqwest::NoProxy::from_env());
+            builder = builder.proxy(proxy);
+        }
+        for path in &self.ca_certs {
+            let pem = read_file(path)?;
+            let certs = reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| {
+                anyhow::anyhow!("CA証明書を読み込めません ({}): {}", path.display(), e)
+            })?;
+            if certs.is_empty() {
+                anyhow::bail!("CA証明書が含まれていません: {}", path.display());
+            }
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some((cert, key)) = &self.client_identity {
+            let mut pem = read_file(cert)?;
+            if let Some(key) = key {
+                pem.push(b'\n');
+                pem.extend(read_file(key)?);
+            }
+            let identity = reqwest::Identity::from_pem(&pem).map_err(|e| {
+                anyhow::anyhow!(
+                    "クライアント証明書を読み込めません ({}): {}",
+                    cert.display(),
+                    e
+                )
+            })?;
+            builder = builder.identity(identity);
+        }
+        builder
+            .build()
+            .map_err(|e| anyhow::anyhow!("HTTPクライアントの初期化に失敗: {}", e))
+    }
+}
+
+fn read_file(path: &std::path::Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| anyhow::anyhow!("{} を読み込めません: {}", path.display(), e))
+}
 
 pub struct WebhookSender {
     url: String,
     client: reqwest::Client,
+    /// HMAC署名用の共有シークレット
+    secret: Option<String>,
+    /// 送信先の設定で指定されたヘッダー（認証情報を含みうる）
+    headers: Vec<(String, String)>,
 }
 
 impl WebhookSender {
+    /// デフォルト設定のクライアントで作る
+    #[cfg(test)]
     pub fn new(url: String) -> Self {
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(10))
-            .build()
-            .expect("HTTPクライアントの初期化に失敗");
-        WebhookSender { url, client }
+        Self::with_options(url, &ClientOptions::default()).expect("HTTPクライアントの初期化に失敗")
+    }
+
+    /// プロキシや証明書を指定してクライアントを作る
+    pub fn with_options(url: String, options: &ClientOptions) -> Result<Self> {
+        Ok(WebhookSender {
+            url,
+            client: options.build_client()?,
+            secret: None,
+            headers: Vec::new(),
+        })
+    }
+
+    /// すべてのリクエストに付けるヘッダーを設定する
+    pub fn with_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.headers = headers;
+        self
     }
 
-    pub async fn send(&self, payload: Value) -> Result<()> {
+    /// リクエストごとにボディへHMAC-SHA256署名を付ける
+    pub fn with_secret(mut self, secret: String) -> Self {
+        self.secret = Some(secret);
+        self
+    }
+
+    /// 設定されたヘッダーの名前（値は表示しない）
+    pub fn header_names(&self) -> Vec<&str> {
+        self.headers.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// ボディを一度だけシリアライズし、ヘッダーを付けたリクエストを組み立てる
+    ///
+    /// 送信先のヘッダーを付けられない場合は、付けずに送ると認証エラーなどで原因がわかりにくいのでエラーにする
+    fn request(&self, payload: &Payload, body: &[u8]) -> Result<reqwest::RequestBuilder> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, payload.content_type())
+            .body(body.to_vec());
+        for (name, value) in &payload.headers {
+            if !name.eq_ignore_ascii_case("content-type") {
+                request = request.header(name.as_str(), value.as_str());
+            }
+        }
+        for (name, value) in &self.headers {
+            let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| anyhow::anyhow!("headers: ヘッダー名が不正です: {}", name))?;
+            // 値はエラーやデバッグ出力に表示させない
+            let mut value = reqwest::header::HeaderValue::from_str(value).map_err(|_| {
+                anyhow::anyhow!(
+                    "headers.{}: 値にヘッダーに使えない文字（改行など）が含まれています",
+                    name
+                )
+            })?;
+            value.set_sensitive(true);
+            request = request.header(name, value);
+        }
+        if let Some(secret) = &self.secret {
+            // リトライ時も送信時刻で署名し直す
+            let timestamp = chrono::Utc::now().timestamp();
+            request = request
+                .header(signature::TIMESTAMP_HEADER, timestamp.to_string())
+                .header(
+                    signature::SIGNATURE_HEADER,
+                    signature::sign(secret, timestamp, body),
+                );
+        }
+        Ok(request)
+    }
+
+    pub async fn send(&self, payload: impl Into<Payload>) -> Result<()> {
+        let payload = payload.into();
+        let body = serde_json::to_vec(&payload.body)?;
         let mut last_err = None;
         let delays = [100u64, 200, 400];
 
         for (attempt, delay_ms) in delays.iter().enumerate() {
-            match self.client.post(&self.url).json(&payload).send().await {
+            match self.request(&payload, &body)?.send().await {
                 Ok(resp) if resp.status().is_success() => {
                     return Ok(());
                 }
@@ -43,6 +215,25 @@ impl WebhookSender {
 
         Err(last_err.unwrap_or_else(|| anyhow::anyhow!("不明なエラー")))
     }
+
+    /// リトライせずに1回だけ送信し、ステータス・レイテンシ・レスポンスボディを返す
+    pub async fn send_once(&self, payload: impl Into<Payload>) -> Result<SendReport> {
+        let payload = payload.into();
+        let body = serde_json::to_vec(&payload.body)?;
+        let started = Instant::now();
+        let resp = self
+            .request(&payload, &body)?
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("送信エラー: {}", e))?;
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        Ok(SendReport {
+            status,
+            latency: started.elapsed(),
+            body,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -104,6 +295,223 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_send_once_reports_status_and_body() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/webhook"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("no_service"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let url = format!("{}/webhook", mock_server.uri());
+        let sender = WebhookSender::new(url);
+        let report = sender.send_once(json!({"text": "test"})).await.unwrap();
+        // 失敗ステータスでもリトライせずそのまま返す
+        assert_eq!(report.status.as_u16(), 404);
+        assert_eq!(report.body, "no_service");
+    }
+
+    #[tokio::test]
+    async fn test_send_payload_headers() {
+        use wiremock::matchers::header;
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("content-type", "application/cloudevents+json"))
+            .and(header("x-extra", "1"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let sender = WebhookSender::new(mock_server.uri());
+        let payload = Payload::from(json!({"text": "test"}))
+            .with_header("Content-Type", "application/cloudevents+json")
+            .with_header("x-extra", "1");
+        assert!(sender.send(payload).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_send_default_content_type_is_json() {
+        use wiremock::matchers::header;
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("content-type", "application/json"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let sender = WebhookSender::new(mock_server.uri());
+        assert!(sender.send(json!({"text": "test"})).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_send_signed_request() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let sender = WebhookSender::new(mock_server.uri()).with_secret("s3cret".to_string());
+        assert!(sender.send(json!({"text": "test"})).await.is_ok());
+
+        // 受信側と同じ手順で、届いたボディそのものに対する署名を検証する
+        let requests = mock_server.received_requests().await.unwrap();
+        let request = &requests[0];
+        let header = |name: &str| request.headers[name].to_str().unwrap().to_string();
+        let now = chrono::Utc::now().timestamp();
+        signature::verify(
+            "s3cret",
+            &header(signature::TIMESTAMP_HEADER),
+            &request.body,
+            &header(signature::SIGNATURE_HEADER),
+            now,
+            Duration::from_secs(300),
+        )
+        .expect("署名の検証に失敗");
+        assert!(signature::verify(
+            "wrong",
+            &header(signature::TIMESTAMP_HEADER),
+            &request.body,
+            &header(signature::SIGNATURE_HEADER),
+            now,
+            Duration::from_secs(300),
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_unsigned_without_secret() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+
+        let sender = WebhookSender::new(mock_server.uri());
+        assert!(sender.send(json!({"text": "test"})).await.is_ok());
+        let requests = mock_server.received_requests().await.unwrap();
+        assert!(!requests[0]
+            .headers
+            .contains_key(signature::SIGNATURE_HEADER));
+    }
+
+    #[tokio::test]
+    async fn test_send_destination_headers() {
+        use wiremock::matchers::header;
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("authorization", "Bearer t0ken"))
+            .and(header("x-tenant", "acme"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(3)
+            .up_to_n_times(3)
+            .mount(&mock_server)
+            .await;
+
+        let sender = WebhookSender::new(mock_server.uri()).with_headers(vec![
+            ("Authorization".to_string(), "Bearer t0ken".to_string()),
+            ("X-Tenant".to_string(), "acme".to_string()),
+        ]);
+        // 形式によらずすべてのリクエストに付く
+        assert!(sender.send(json!({"text": "test"})).await.is_ok());
+        assert!(sender
+            .send(Payload::from(json!({})).with_header("ce-id", "1"))
+            .await
+            .is_ok());
+        assert!(sender.send_once(json!({"text": "test"})).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_send_rejects_invalid_header_value() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        // 環境変数から末尾の改行ごと読み込んだ場合など
+        let sender = WebhookSender::new(mock_server.uri()).with_headers(vec![(
+            "Authorization".to_string(),
+            "Bearer t0ken\n".to_string(),
+        )]);
+        for result in [
+            sender.send(json!({"text": "test"})).await,
+            sender.send_once(json!({"text": "test"})).await.map(|_| ()),
+        ] {
+            let err = result.unwrap_err().to_string();
+            assert!(err.contains("headers.authorization"), "{}", err);
+            assert!(!err.contains("t0ken"), "値がエラーに含まれている: {}", err);
+        }
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    #[tokio::test]
+    async fn test_send_through_explicit_proxy() {
+        let proxy = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+
+        // 存在しないホスト宛てでもプロキシ経由なら届く
+        let options = ClientOptions {
+            proxy: Some(proxy.uri()),
+            ..Default::default()
+        };
+        let sender =
+            WebhookSender::with_options("http://aloud-code.invalid/hook".to_string(), &options)
+                .unwrap();
+        assert!(sender.send(json!({"text": "test"})).await.is_ok());
+    }
+
+    #[test]
+    fn test_client_options_load_certificates() {
+        let options = ClientOptions {
+            ca_certs: vec![fixture("ca.pem")],
+            client_identity: Some((fixture("client.pem"), Some(fixture("client.key")))),
+            ..Default::default()
+        };
+        assert!(options.build_client().is_ok());
+    }
+
+    #[test]
+    fn test_client_options_reject_bad_files() {
+        let missing = ClientOptions {
+            ca_certs: vec![fixture("missing.pem")],
+            ..Default::default()
+        };
+        let err = missing.build_client().unwrap_err().to_string();
+        assert!(err.contains("missing.pem"), "{}", err);
+
+        // 鍵の入っていないファイルはクライアント証明書として使えない
+        let no_key = ClientOptions {
+            client_identity: Some((fixture("client.pem"), None)),
+            ..Default::default()
+        };
+        assert!(no_key.build_client().is_err());
+
+        let not_a_cert = ClientOptions {
+            ca_certs: vec![fixture("client.key")],
+            ..Default::default()
+        };
+        assert!(not_a_cert.build_client().is_err());
+    }
+
     #[tokio::test]
     async fn test_send_invalid_url() {
         let sender = WebhookSender::new("http://localhost:1".to_string());
@@ -1,5 +1,5 @@
 use serde_json::json;
-use wiremock::matchers::method;
+use wiremock::matchers::{method, path};
 use wiremock::{Mock, MockServer, ResponseTemplate};
 
 fn binary_path() -> std::path::PathBuf {
@@ -33,6 +33,16 @@ impl TestEnv {
             .expect("config.toml書き込み失敗");
     }
 
+    fn run_cli(&self, args: &[&str]) -> std::process::Output {
+        std::process::Command::new(binary_path())
+            .args(args)
+            .env("ALOUD_CODE_CONFIG_FILE", &self.config_file)
+            .env("ALOUD_CODE_STATE_DIR", &self.state_dir)
+            .stdin(std::process::Stdio::null())
+            .output()
+            .expect("バイナリ起動失敗")
+    }
+
     async fn run_hook(&self, event: &str, input_json: &str) -> std::process::Output {
         use std::io::Write;
         use std::process::{Command, Stdio};
@@ -179,7 +189,11 @@ async fn test_stop_hook_sends_assistant_message() {
 
     let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
     let text = body["blocks"][0]["text"]["text"].as_str().unwrap();
-    assert!(text.contains(":robot_face:"), "Claude絵文字がない");
+    assert!(
+        text.contains(":robot_face:"),
+        "Claude絵文字がない: {}",
+        text
+    );
     assert!(
         text.contains("I've completed the task!"),
         "アシスタントメッセージが含まれていない: {}",
@@ -204,6 +218,26 @@ async fn test_toggle_lifecycle() {
     // 初期状態はOFF
     assert!(!sessions_dir.exists(), "初期状態はOFFのはず");
 
+    // 引数の誤ったコマンドはONにせず、使い方を表示してClaudeに渡さない
+    let malformed = json!({
+        "session_id": "lifecycle-session",
+        "hook_event_name": "UserPromptSubmit",
+        "prompt": "/aloud-code:on 90m 2h"
+    });
+    let output = env.run_hook("toggle", &malformed.to_string()).await;
+    assert!(output.status.success());
+    let stdout: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stdout["decision"], "block");
+    assert!(
+        stdout["reason"]
+            .as_str()
+            .unwrap()
+            .starts_with("aloud-code: usage: /aloud-code:on "),
+        "{}",
+        stdout
+    );
+    assert!(!sessions_dir.join("lifecycle-session").exists());
+
     // /aloud-code:on でONにする
     let toggle_on = json!({
         "session_id": "lifecycle-session",
@@ -216,6 +250,10 @@ async fn test_toggle_lifecycle() {
         sessions_dir.join("lifecycle-session").exists(),
         "enable後はフラグが存在するはず"
     );
+    // トグルコマンドはClaudeに渡さず、確認だけを表示する
+    let stdout: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stdout["decision"], "block");
+    assert_eq!(stdout["reason"], "aloud-code: streaming on");
 
     // /aloud-code:off でOFFにする
     let toggle_off = json!({
@@ -229,6 +267,59 @@ async fn test_toggle_lifecycle() {
         !sessions_dir.join("lifecycle-session").exists(),
         "disable後はフラグが消えるはず"
     );
+    let stdout: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stdout["decision"], "block");
+    assert_eq!(stdout["reason"], "aloud-code: streaming off");
+}
+
+#[tokio::test]
+async fn test_legacy_flag_file_becomes_session_state() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[webhook]\nurl = \"{}\"\nformat = \"event\"\n",
+            mock_server.uri()
+        ),
+    )
+    .unwrap();
+
+    // 以前のバージョンが書いた、直前の通し番号だけのフラグファイル
+    let sessions_dir = env.state_dir.join("sessions");
+    std::fs::create_dir_all(&sessions_dir).unwrap();
+    std::fs::write(sessions_dir.join("legacy-session"), "4").unwrap();
+
+    let prompt = json!({
+        "session_id": "legacy-session",
+        "cwd": "/home/user/my-project",
+        "prompt": "Still streaming?"
+    });
+    let output = env.run_hook("user-prompt", &prompt.to_string()).await;
+    assert!(
+        output.status.success(),
+        "hook失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let requests = mock_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["sequence"], 5, "通し番号が引き継がれていない");
+
+    let state: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(sessions_dir.join("legacy-session")).unwrap(),
+    )
+    .expect("状態がJSONで保存されていない");
+    assert_eq!(state["last_sequence"], 5);
+    assert_eq!(state["prompts"], 1);
+    assert!(state["last_activity_at"].is_string());
 }
 
 #[tokio::test]
@@ -269,3 +360,1516 @@ async fn test_no_webhook_for_different_session() {
         "異なるセッションIDなのにWebhookが届いた"
     );
 }
+
+#[tokio::test]
+async fn test_test_subcommand_reports_each_destination() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+        .mount(&mock_server)
+        .await;
+
+    env.set_webhook_url(&format!("{}/webhook", mock_server.uri()));
+
+    let output = env.run_cli(&["test"]);
+    assert!(
+        output.status.success(),
+        "test失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[default]"), "送信先名がない: {}", stdout);
+    assert!(stdout.contains("200 OK"), "ステータスがない: {}", stdout);
+    assert!(stdout.contains("latency:"), "レイテンシがない: {}", stdout);
+
+    // 実際のフォーマッタで整形されたメッセージが届いている
+    let requests = mock_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert!(body["username"].as_str().unwrap().ends_with("[aloud-co]"));
+}
+
+#[tokio::test]
+async fn test_test_subcommand_fails_on_error_status() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(404).set_body_string("no_service"))
+        .mount(&mock_server)
+        .await;
+
+    env.set_webhook_url(&format!("{}/webhook", mock_server.uri()));
+
+    let output = env.run_cli(&["test"]);
+    assert!(!output.status.success(), "失敗ステータスなのに成功した");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("404"), "ステータスがない: {}", stdout);
+    assert!(
+        stdout.contains("no_service"),
+        "レスポンスボディがない: {}",
+        stdout
+    );
+}
+
+#[tokio::test]
+async fn test_config_init_then_validate() {
+    let env = TestEnv::new();
+
+    let output = env.run_cli(&["config", "init", "--url", "https://example.com/hook"]);
+    assert!(
+        output.status.success(),
+        "config init失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = std::fs::read_to_string(&env.config_file).expect("設定ファイルがない");
+    assert!(content.contains("url = \"https://example.com/hook\""));
+
+    // 既存ファイルは --force なしでは上書きしない
+    let output = env.run_cli(&["config", "init"]);
+    assert!(!output.status.success(), "既存ファイルを上書きした");
+
+    let output = env.run_cli(&["config", "validate"]);
+    assert!(
+        output.status.success(),
+        "config validate失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[tokio::test]
+async fn test_config_validate_explains_unknown_key() {
+    let env = TestEnv::new();
+    std::fs::write(
+        &env.config_file,
+        "[webhooks]\nurl = \"https://example.com/hook\"\n",
+    )
+    .unwrap();
+
+    let output = env.run_cli(&["config", "validate"]);
+    assert!(!output.status.success(), "不明なキーなのに成功した");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("line 1, column 2"),
+        "位置がない: {}",
+        stderr
+    );
+    assert!(stderr.contains("webhooks"), "キー名がない: {}", stderr);
+}
+
+#[tokio::test]
+async fn test_hook_logs_invalid_config() {
+    let env = TestEnv::new();
+    std::fs::write(
+        &env.config_file,
+        "[webhooks]\nurl = \"https://example.com\"\n",
+    )
+    .unwrap();
+
+    let toggle_on = json!({
+        "session_id": "invalid-config-session",
+        "prompt": "/aloud-code:on"
+    });
+    env.run_hook("toggle", &toggle_on.to_string()).await;
+
+    let input = json!({
+        "session_id": "invalid-config-session",
+        "cwd": "/tmp",
+        "prompt": "hello"
+    });
+    let output = env.run_hook("user-prompt", &input.to_string()).await;
+    assert!(output.status.success(), "hookは失敗させない");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("設定ファイルが不正"),
+        "ログがない: {}",
+        stderr
+    );
+}
+
+#[tokio::test]
+async fn test_project_config_layered_over_global() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    env.set_webhook_url(&format!("{}/webhook", mock_server.uri()));
+
+    // リポジトリ直下に .aloud-code.toml を置き、サブディレクトリから実行する
+    let repo = env.state_dir.join("repo");
+    let sub_dir = repo.join("src");
+    std::fs::create_dir_all(&sub_dir).unwrap();
+    std::fs::write(
+        repo.join(".aloud-code.toml"),
+        "labels = [\"backend\"]\n\n[webhook]\nchannel = \"#team-stream\"\n\n[redact]\npatterns = [\"sk-[a-z0-9]+\"]\n",
+    )
+    .unwrap();
+
+    let toggle_on = json!({
+        "session_id": "layered-session",
+        "prompt": "/aloud-code:on"
+    });
+    env.run_hook("toggle", &toggle_on.to_string()).await;
+
+    let input = json!({
+        "session_id": "layered-session",
+        "cwd": sub_dir.to_string_lossy(),
+        "prompt": "my key is sk-abc123"
+    });
+    let output = env.run_hook("user-prompt", &input.to_string()).await;
+    assert!(
+        output.status.success(),
+        "user-prompt hook失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let requests = mock_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["channel"], "#team-stream");
+    let text = body["blocks"][0]["text"]["text"].as_str().unwrap();
+    assert!(text.contains("[REDACTED]"), "マスクされていない: {}", text);
+    assert!(
+        !text.contains("sk-abc123"),
+        "秘密情報が送信された: {}",
+        text
+    );
+    assert!(body["blocks"][1]["elements"][0]["text"]
+        .as_str()
+        .unwrap()
+        .contains("backend"));
+}
+
+#[tokio::test]
+async fn test_config_show_effective_lists_sources() {
+    let env = TestEnv::new();
+    env.set_webhook_url("https://example.com/hook");
+
+    let output = std::process::Command::new(binary_path())
+        .args(["config", "show", "--effective"])
+        .env("ALOUD_CODE_CONFIG_FILE", &env.config_file)
+        .env("ALOUD_CODE_STATE_DIR", &env.state_dir)
+        .env("ALOUD_CODE_WEBHOOK_CHANNEL", "#from-env")
+        .current_dir(&env.state_dir)
+        .output()
+        .expect("バイナリ起動失敗");
+    assert!(
+        output.status.success(),
+        "config show失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("url = \"********\"  # global"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("channel = \"#from-env\"  # env (ALOUD_CODE_WEBHOOK_CHANNEL)"),
+        "{}",
+        stdout
+    );
+}
+
+#[tokio::test]
+async fn test_webhook_url_from_env_and_command() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+        .mount(&mock_server)
+        .await;
+
+    let webhook_url = format!("{}/webhook", mock_server.uri());
+
+    // ${env:NAME} による参照
+    std::fs::write(
+        &env.config_file,
+        "[webhook]\nurl = \"${env:ALOUD_CODE_TEST_WEBHOOK}\"\n",
+    )
+    .unwrap();
+    let output = std::process::Command::new(binary_path())
+        .arg("test")
+        .env("ALOUD_CODE_CONFIG_FILE", &env.config_file)
+        .env("ALOUD_CODE_STATE_DIR", &env.state_dir)
+        .env("ALOUD_CODE_TEST_WEBHOOK", &webhook_url)
+        .output()
+        .expect("バイナリ起動失敗");
+    assert!(
+        output.status.success(),
+        "env参照でのtest失敗: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    // url_command による取得
+    std::fs::write(
+        &env.config_file,
+        format!("[webhook]\nurl_command = \"echo {}\"\n", webhook_url),
+    )
+    .unwrap();
+    let output = env.run_cli(&["test"]);
+    assert!(
+        output.status.success(),
+        "url_commandでのtest失敗: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    let requests = mock_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+}
+
+#[tokio::test]
+async fn test_custom_template_renders_payload() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            r#"[webhook]
+url = "{}/webhook"
+template = "compact"
+
+[templates.compact]
+user = '{{"kind": {{{{ event | tojson }}}}, "body": {{{{ prompt | tojson }}}}, "project": {{{{ project | tojson }}}}}}'
+assistant = '{{"kind": {{{{ event | tojson }}}}, "body": {{{{ message | tojson }}}}}}'
+"#,
+            mock_server.uri()
+        ),
+    )
+    .unwrap();
+
+    let toggle_on = json!({
+        "session_id": "template-session",
+        "prompt": "/aloud-code:on"
+    });
+    env.run_hook("toggle", &toggle_on.to_string()).await;
+
+    let input = json!({
+        "session_id": "template-session",
+        "cwd": "/home/user/templated",
+        "prompt": "render me"
+    });
+    let output = env.run_hook("user-prompt", &input.to_string()).await;
+    assert!(
+        output.status.success(),
+        "user-prompt hook失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let requests = mock_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(
+        body,
+        json!({"kind": "user-prompt", "body": "render me", "project": "templated"})
+    );
+}
+
+#[tokio::test]
+async fn test_event_destination_receives_envelope() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[webhook]\nurl = \"{uri}/slack\"\n\n[destinations.ingest]\nurl = \"{uri}/events\"\nformat = \"event\"\n",
+            uri = mock_server.uri()
+        ),
+    )
+    .unwrap();
+
+    let toggle_on = json!({
+        "session_id": "event-session",
+        "prompt": "/aloud-code:on"
+    });
+    env.run_hook("toggle", &toggle_on.to_string()).await;
+
+    let prompt = json!({
+        "session_id": "event-session",
+        "cwd": "/home/user/ingest-project",
+        "prompt": "first",
+        "model": "claude-sonnet-4-6"
+    });
+    let output = env.run_hook("user-prompt", &prompt.to_string()).await;
+    assert!(
+        output.status.success(),
+        "user-prompt hook失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stop = json!({
+        "session_id": "event-session",
+        "cwd": "/home/user/ingest-project",
+        "last_assistant_message": "second"
+    });
+    let output = env.run_hook("stop", &stop.to_string()).await;
+    assert!(output.status.success());
+
+    let requests = mock_server.received_requests().await.unwrap();
+    let events: Vec<serde_json::Value> = requests
+        .iter()
+        .filter(|r| r.url.path() == "/events")
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    assert_eq!(events.len(), 2, "イベント送信先に2件届いていない");
+    assert_eq!(events[0]["schema_version"], 1);
+    assert_eq!(events[0]["type"], "prompt");
+    assert_eq!(events[0]["sequence"], 1);
+    assert_eq!(events[0]["project"], "ingest-project");
+    assert_eq!(events[0]["model"], "claude-sonnet-4-6");
+    assert_eq!(events[0]["content"]["text"], "first");
+    assert_eq!(events[1]["type"], "response");
+    assert_eq!(events[1]["sequence"], 2);
+    assert_eq!(events[1]["content"]["role"], "assistant");
+
+    // Slack形式の送信先にも従来どおり届く
+    let slack_count = requests.iter().filter(|r| r.url.path() == "/slack").count();
+    assert_eq!(slack_count, 2);
+}
+
+#[tokio::test]
+async fn test_cloudevents_destinations() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(202))
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[destinations.structured]\nurl = \"{uri}/structured\"\nformat = \"cloudevents\"\n\n\
+             [destinations.binary]\nurl = \"{uri}/binary\"\nformat = \"cloudevents\"\ncloudevents_mode = \"binary\"\n",
+            uri = mock_server.uri()
+        ),
+    )
+    .unwrap();
+
+    let toggle_on = json!({
+        "session_id": "ce-session",
+        "prompt": "/aloud-code:on"
+    });
+    env.run_hook("toggle", &toggle_on.to_string()).await;
+
+    let input = json!({
+        "session_id": "ce-session",
+        "cwd": "/home/user/bus-project",
+        "prompt": "publish me"
+    });
+    let output = env.run_hook("user-prompt", &input.to_string()).await;
+    assert!(
+        output.status.success(),
+        "user-prompt hook失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let requests = mock_server.received_requests().await.unwrap();
+    let structured = requests
+        .iter()
+        .find(|r| r.url.path() == "/structured")
+        .expect("structuredに届いていない");
+    assert_eq!(
+        structured.headers["content-type"],
+        "application/cloudevents+json"
+    );
+    let body: serde_json::Value = serde_json::from_slice(&structured.body).unwrap();
+    assert_eq!(body["specversion"], "1.0");
+    assert_eq!(body["type"], "dev.aloud-code.prompt");
+    let id = body["id"].as_str().unwrap().to_string();
+    assert_eq!(id.len(), 36, "UUID形式ではない: {}", id);
+    assert_eq!(body["source"], "/aloud-code/bus-project/ce-session");
+    assert_eq!(body["data"]["content"]["text"], "publish me");
+
+    let binary = requests
+        .iter()
+        .find(|r| r.url.path() == "/binary")
+        .expect("binaryに届いていない");
+    assert_eq!(binary.headers["ce-specversion"], "1.0");
+    assert_eq!(binary.headers["ce-type"], "dev.aloud-code.prompt");
+    assert_eq!(binary.headers["ce-id"].to_str().unwrap().len(), 36);
+    assert_eq!(binary.headers["content-type"], "application/json");
+    let body: serde_json::Value = serde_json::from_slice(&binary.body).unwrap();
+    assert_eq!(body["content"]["text"], "publish me");
+}
+
+#[tokio::test]
+async fn test_signed_destination() {
+    use hmac::{Hmac, Mac};
+
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[webhook]\nurl = \"{}\"\nsecret = \"${{env:ALOUD_CODE_TEST_SECRET}}\"\n",
+            mock_server.uri()
+        ),
+    )
+    .unwrap();
+    let output = std::process::Command::new(binary_path())
+        .arg("test")
+        .env("ALOUD_CODE_CONFIG_FILE", &env.config_file)
+        .env("ALOUD_CODE_STATE_DIR", &env.state_dir)
+        .env("ALOUD_CODE_TEST_SECRET", "s3cret")
+        .output()
+        .expect("バイナリ起動失敗");
+    assert!(
+        output.status.success(),
+        "署名付きのtest失敗: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    // 受信側の手順: `<timestamp>.<body>` のHMAC-SHA256を計算して比較する
+    let requests = mock_server.received_requests().await.unwrap();
+    let request = &requests[0];
+    let timestamp = request.headers["x-signature-timestamp"].to_str().unwrap();
+    let signature = request.headers["x-signature-256"].to_str().unwrap();
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"s3cret").unwrap();
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(&request.body);
+    let expected = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+    assert_eq!(signature, expected);
+}
+
+#[tokio::test]
+async fn test_destination_headers_hidden_in_output() {
+    use wiremock::matchers::header;
+
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .and(header("authorization", "Bearer t0ken-value"))
+        .and(header("x-tenant", "acme"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[webhook]\nurl = \"{}\"\nbearer_token = \"${{env:ALOUD_CODE_TEST_TOKEN}}\"\n\n\
+             [webhook.headers]\nX-Tenant = \"acme\"\n",
+            mock_server.uri()
+        ),
+    )
+    .unwrap();
+    let output = std::process::Command::new(binary_path())
+        .arg("test")
+        .env("ALOUD_CODE_CONFIG_FILE", &env.config_file)
+        .env("ALOUD_CODE_STATE_DIR", &env.state_dir)
+        .env("ALOUD_CODE_TEST_TOKEN", "t0ken-value")
+        .output()
+        .expect("バイナリ起動失敗");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "test失敗: {}", stdout);
+    assert!(stdout.contains("X-Tenant, Authorization"), "{}", stdout);
+    assert!(
+        !stdout.contains("t0ken-value"),
+        "トークンが表示された: {}",
+        stdout
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_environment_variables() {
+    let env = TestEnv::new();
+    let proxy = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&proxy)
+        .await;
+
+    // 解決できないホスト宛てなので、プロキシを経由した場合だけ成功する
+    std::fs::write(
+        &env.config_file,
+        "[webhook]\nurl = \"http://aloud-code.invalid/hook\"\n",
+    )
+    .unwrap();
+    let run = |no_proxy: &str| {
+        std::process::Command::new(binary_path())
+            .arg("test")
+            .env("ALOUD_CODE_CONFIG_FILE", &env.config_file)
+            .env("ALOUD_CODE_STATE_DIR", &env.state_dir)
+            .env("HTTP_PROXY", proxy.uri())
+            .env("NO_PROXY", no_proxy)
+            .env_remove("http_proxy")
+            .env_remove("no_proxy")
+            .output()
+            .expect("バイナリ起動失敗")
+    };
+
+    let output = run("");
+    assert!(
+        output.status.success(),
+        "HTTP_PROXY経由で送信できない: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    assert_eq!(proxy.received_requests().await.unwrap().len(), 1);
+
+    let output = run("aloud-code.invalid");
+    assert!(!output.status.success(), "NO_PROXYが無視された");
+    assert_eq!(proxy.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_archive_destination_appends_events() {
+    let env = TestEnv::new();
+    std::fs::write(
+        &env.config_file,
+        "[destinations.log]\ntype = \"archive\"\nretention_days = 30\n",
+    )
+    .unwrap();
+
+    let toggle_on = json!({
+        "session_id": "archive-session",
+        "prompt": "/aloud-code:on"
+    });
+    env.run_hook("toggle", &toggle_on.to_string()).await;
+
+    let prompt = json!({
+        "session_id": "archive-session",
+        "cwd": "/home/user/my-project",
+        "prompt": "Archive me"
+    });
+    let stop = json!({
+        "session_id": "archive-session",
+        "cwd": "/home/user/my-project",
+        "last_assistant_message": "Archived."
+    });
+    for (event, input) in [("user-prompt", &prompt), ("stop", &stop)] {
+        let output = env.run_hook(event, &input.to_string()).await;
+        assert!(
+            output.status.success(),
+            "{} hook失敗: {}",
+            event,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    // 日付ディレクトリ名は送信時のUTC日付
+    let archive = env.state_dir.join("archive");
+    let day = std::fs::read_dir(&archive)
+        .expect("archiveディレクトリがない")
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let content = std::fs::read_to_string(day.join("archive-session.jsonl")).unwrap();
+    let events: Vec<serde_json::Value> = content
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["type"], "prompt");
+    assert_eq!(events[0]["content"]["text"], "Archive me");
+    assert_eq!(events[1]["type"], "response");
+    assert_eq!(events[1]["sequence"], 2);
+}
+
+#[tokio::test]
+async fn test_markdown_transcript_destination() {
+    let env = TestEnv::new();
+    let notes = env.state_dir.join("notes");
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[destinations.notes]\ntype = \"markdown\"\ndirectory = {:?}\n",
+            notes.display().to_string()
+        ),
+    )
+    .unwrap();
+
+    let toggle_on = json!({
+        "session_id": "md-session",
+        "prompt": "/aloud-code:on"
+    });
+    env.run_hook("toggle", &toggle_on.to_string()).await;
+    let prompt = json!({
+        "session_id": "md-session",
+        "cwd": "/home/user/design-doc",
+        "prompt": "Sketch the API"
+    });
+    let stop = json!({
+        "session_id": "md-session",
+        "cwd": "/home/user/design-doc",
+        "last_assistant_message": "Here is a sketch."
+    });
+    env.run_hook("user-prompt", &prompt.to_string()).await;
+    env.run_hook("stop", &stop.to_string()).await;
+
+    let content = std::fs::read_to_string(notes.join("design-doc-md-session.md"))
+        .expect("トランスクリプトがない");
+    assert!(
+        content.starts_with("# design-doc · session md-sessi"),
+        "{}",
+        content
+    );
+    let user = content.find("## 👤 User").expect("ユーザーの節がない");
+    let claude = content.find("## 🤖 Claude").expect("Claudeの節がない");
+    assert!(user < claude, "順序が逆: {}", content);
+    assert!(content.contains("Sketch the API"));
+    assert!(content.contains("Here is a sketch."));
+}
+
+/// Claude Codeのセッションログを `<claude_dir>/projects/<dir>/<session_id>.jsonl` に書く
+fn write_session_log(claude_dir: &std::path::Path, session_id: &str) -> std::path::PathBuf {
+    let dir = claude_dir.join("projects").join("-home-user-old-project");
+    std::fs::create_dir_all(&dir).unwrap();
+    let lines = [
+        json!({"type": "user", "sessionId": session_id, "cwd": "/home/user/old-project",
+               "timestamp": "2026-01-02T03:00:00Z",
+               "message": {"role": "user", "content": "Refactor the parser"}}),
+        json!({"type": "assistant", "sessionId": session_id, "timestamp": "2026-01-02T03:00:05Z",
+               "message": {"id": "m1", "role": "assistant", "model": "claude-sonnet-4-6",
+                           "content": [{"type": "text", "text": "Refactored."}]}}),
+        json!({"type": "user", "sessionId": session_id, "timestamp": "2026-01-02T03:01:00Z",
+               "message": {"role": "user", "content": "Ship it"}}),
+    ];
+    let path = dir.join(format!("{}.jsonl", session_id));
+    let content: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+    std::fs::write(&path, content.join("\n")).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn test_replay_session_log() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[webhook]\nurl = \"{}\"\nformat = \"event\"\n",
+            mock_server.uri()
+        ),
+    )
+    .unwrap();
+    let claude_dir = env.state_dir.join("claude");
+    let path = write_session_log(&claude_dir, "old-session");
+    let run = |args: &[&str]| {
+        std::process::Command::new(binary_path())
+            .arg("replay")
+            .args(args)
+            .env("ALOUD_CODE_CONFIG_FILE", &env.config_file)
+            .env("ALOUD_CODE_STATE_DIR", &env.state_dir)
+            .env("CLAUDE_CONFIG_DIR", &claude_dir)
+            .output()
+            .expect("バイナリ起動失敗")
+    };
+
+    // --dry-run では送信せずペイロードを表示する
+    let output = run(&[path.to_str().unwrap(), "--dry-run"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "dry-run失敗: {}", stdout);
+    assert!(stdout.contains("Refactor the parser"), "{}", stdout);
+    assert!(stdout.contains("#3 user-prompt"), "{}", stdout);
+    assert!(mock_server.received_requests().await.unwrap().is_empty());
+
+    // セッションIDでも指定できる
+    let output = run(&["old-session", "--interval-ms", "0"]);
+    assert!(
+        output.status.success(),
+        "replay失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let requests = mock_server.received_requests().await.unwrap();
+    let bodies: Vec<serde_json::Value> = requests
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    assert_eq!(bodies.len(), 3);
+    assert_eq!(bodies[0]["content"]["text"], "Refactor the parser");
+    assert_eq!(bodies[0]["timestamp"], "2026-01-02T03:00:00.000Z");
+    assert_eq!(bodies[0]["project"], "old-project");
+    assert_eq!(bodies[1]["content"]["text"], "Refactored.");
+    assert_eq!(bodies[1]["model"], "claude-sonnet-4-6");
+    assert_eq!(bodies[2]["sequence"], 3);
+}
+
+#[tokio::test]
+async fn test_export_session_as_html() {
+    let env = TestEnv::new();
+    std::fs::write(
+        &env.config_file,
+        "[destinations.log]\ntype = \"archive\"\n\n[redact]\npatterns = [\"Ship\"]\n",
+    )
+    .unwrap();
+    let claude_dir = env.state_dir.join("claude");
+    write_session_log(&claude_dir, "old-session");
+    let run = |args: &[&str]| {
+        std::process::Command::new(binary_path())
+            .arg("export")
+            .args(args)
+            .current_dir(&env.state_dir)
+            .env("ALOUD_CODE_CONFIG_FILE", &env.config_file)
+            .env("ALOUD_CODE_STATE_DIR", &env.state_dir)
+            .env("CLAUDE_CONFIG_DIR", &claude_dir)
+            .output()
+            .expect("バイナリ起動失敗")
+    };
+
+    // 出力先を省略すると <プロジェクト名>-<短縮ID>.html に書き出す
+    let output = run(&["--html", "old-session"]);
+    assert!(
+        output.status.success(),
+        "export失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let path = env.state_dir.join("old-project-old-sess.html");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "old-project-old-sess.html"
+    );
+    let html = std::fs::read_to_string(&path).expect("HTMLが書き出されていない");
+    assert!(html.contains("<h1>old-project</h1>"), "{}", html);
+    assert!(html.contains("Refactor the parser"));
+    assert!(html.contains("Refactored."));
+    assert!(!html.contains("Ship it"), "マスクされていない");
+
+    // セッションログがなければローカルアーカイブから書き出す
+    let toggle_on = json!({
+        "session_id": "archived-only",
+        "prompt": "/aloud-code:on"
+    });
+    env.run_hook("toggle", &toggle_on.to_string()).await;
+    let prompt = json!({
+        "session_id": "archived-only",
+        "cwd": "/home/user/my-project",
+        "prompt": "From the archive"
+    });
+    env.run_hook("user-prompt", &prompt.to_string()).await;
+    let output = run(&["--html", "archived-only", "-o", "out.html"]);
+    assert!(
+        output.status.success(),
+        "アーカイブからのexport失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let html = std::fs::read_to_string(env.state_dir.join("out.html")).unwrap();
+    assert!(html.contains("From the archive"), "{}", html);
+
+    // 形式の指定は必須
+    let output = run(&["old-session"]);
+    assert!(!output.status.success());
+    // どこにも記録がないセッション
+    let output = run(&["--html", "missing-session"]);
+    assert!(!output.status.success());
+}
+
+#[tokio::test]
+async fn test_preview_mode_does_not_send() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[webhook]\nurl = \"{}\"\n\n[redact]\npatterns = [\"hunter2\"]\n",
+            mock_server.uri()
+        ),
+    )
+    .unwrap();
+
+    let toggle = |prompt: &str| {
+        json!({
+            "session_id": "preview-session",
+            "prompt": prompt
+        })
+        .to_string()
+    };
+    let prompt = json!({
+        "session_id": "preview-session",
+        "cwd": "/home/user/my-project",
+        "prompt": "My password is hunter2"
+    });
+
+    // /aloud-code:preview ではペイロードをファイルに書き出すだけで送信しない
+    env.run_hook("toggle", &toggle("/aloud-code:preview")).await;
+    let output = env.run_hook("user-prompt", &prompt.to_string()).await;
+    assert!(
+        output.status.success(),
+        "hook失敗: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(mock_server.received_requests().await.unwrap().is_empty());
+    let preview = std::fs::read_to_string(env.state_dir.join("previews/preview-session.log"))
+        .expect("プレビューが書き出されていない");
+    assert!(preview.contains("#1 user-prompt → default"), "{}", preview);
+    assert!(preview.contains("My password is [REDACTED]"), "{}", preview);
+    assert!(!preview.contains("hunter2"));
+    assert!(!preview.contains(&mock_server.uri()), "URLを書き出している");
+
+    // /aloud-code:on で実際の送信に切り替わる
+    env.run_hook("toggle", &toggle("/aloud-code:on")).await;
+    env.run_hook("user-prompt", &prompt.to_string()).await;
+    assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+
+    // ALOUD_CODE_DRY_RUN は有効なセッションすべてに効く
+    let dry_run_file = env.state_dir.join("dry-run.log");
+    let mut child = std::process::Command::new(binary_path())
+        .args(["hook", "user-prompt"])
+        .env("ALOUD_CODE_CONFIG_FILE", &env.config_file)
+        .env("ALOUD_CODE_STATE_DIR", &env.state_dir)
+        .env("ALOUD_CODE_DRY_RUN", &dry_run_file)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .expect("バイナリ起動失敗");
+    {
+        use std::io::Write;
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(prompt.to_string().as_bytes()).unwrap();
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+    let dry_run = std::fs::read_to_string(&dry_run_file).expect("書き出されていない");
+    assert!(dry_run.contains("My password is [REDACTED]"), "{}", dry_run);
+}
+
+#[tokio::test]
+async fn test_session_end_and_prune() {
+    let env = TestEnv::new();
+    let sessions_dir = env.state_dir.join("sessions");
+    let toggle_on = |session_id: &str| {
+        json!({
+            "session_id": session_id,
+            "prompt": "/aloud-code:on"
+        })
+        .to_string()
+    };
+
+    // SessionEnd で状態が消え、同じIDで再開してもOFFから始まる
+    env.run_hook("toggle", &toggle_on("ended-session")).await;
+    assert!(sessions_dir.join("ended-session").exists());
+    let end = json!({
+        "session_id": "ended-session",
+        "hook_event_name": "SessionEnd",
+        "reason": "prompt_input_exit"
+    });
+    let output = env.run_hook("session-end", &end.to_string()).await;
+    assert!(output.status.success());
+    assert!(!sessions_dir.join("ended-session").exists());
+
+    // 長期間操作のないセッションは prune で消える
+    env.run_hook("toggle", &toggle_on("active-session")).await;
+    std::fs::write(
+        sessions_dir.join("stale-session"),
+        json!({"activated_at": "2020-01-01T00:00:00Z"}).to_string(),
+    )
+    .unwrap();
+    let output = env.run_cli(&["sessions", "prune"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "prune失敗: {}", stdout);
+    assert!(
+        stdout.contains("removed stale-session (last active 2020-01-01T00:00:00Z"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("1 session(s) inactive for more than 7 days removed"),
+        "{}",
+        stdout
+    );
+    assert!(!sessions_dir.join("stale-session").exists());
+    assert!(sessions_dir.join("active-session").exists());
+
+    // 期限切れのセッションには送らず、状態を消す
+    std::fs::write(
+        &env.config_file,
+        "[webhook]\nurl = \"http://127.0.0.1:9/unreachable\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        sessions_dir.join("resumed-session"),
+        json!({"last_activity_at": "2020-01-01T00:00:00Z"}).to_string(),
+    )
+    .unwrap();
+    let prompt = json!({
+        "session_id": "resumed-session",
+        "cwd": "/home/user/my-project",
+        "prompt": "Back after a long break"
+    });
+    let output = env.run_hook("user-prompt", &prompt.to_string()).await;
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("resumed-session をOFFにしました"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!sessions_dir.join("resumed-session").exists());
+}
+
+#[tokio::test]
+async fn test_timed_activation() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    env.set_webhook_url(&mock_server.uri());
+    let sessions_dir = env.state_dir.join("sessions");
+    let prompt = |text: &str| {
+        json!({
+            "session_id": "timed-session",
+            "cwd": "/home/user/my-project",
+            "prompt": text
+        })
+        .to_string()
+    };
+
+    // 時間指定のトグルコマンド自体は送らない
+    env.run_hook("toggle", &prompt("/aloud-code:on 90m")).await;
+    env.run_hook("user-prompt", &prompt("/aloud-code:on 90m"))
+        .await;
+    assert!(mock_server.received_requests().await.unwrap().is_empty());
+    let state_path = sessions_dir.join("timed-session");
+    let mut state: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&state_path).unwrap()).unwrap();
+    let expires_at: chrono::DateTime<chrono::Utc> =
+        state["expires_at"].as_str().unwrap().parse().unwrap();
+    let remaining = expires_at - chrono::Utc::now();
+    assert!(
+        remaining > chrono::Duration::minutes(89) && remaining <= chrono::Duration::minutes(90),
+        "終了時刻がずれている: {}",
+        expires_at
+    );
+
+    env.run_hook("user-prompt", &prompt("Within the hour"))
+        .await;
+    assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+
+    // 時間が過ぎると送らず、次のプロンプトで終了を知らせる
+    state["expires_at"] = json!("2020-01-01T00:00:00Z");
+    std::fs::write(&state_path, state.to_string()).unwrap();
+    env.run_hook("user-prompt", &prompt("After the hour")).await;
+    assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+
+    let output = env.run_hook("toggle", &prompt("After the hour")).await;
+    let stdout: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        stdout["systemMessage"],
+        "aloud-code: streaming ended (time limit reached)"
+    );
+    assert!(!state_path.exists());
+    let output = env.run_hook("toggle", &prompt("Again")).await;
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "{}");
+}
+
+#[tokio::test]
+async fn test_toggle_selects_destination() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[webhook]\nurl = \"{0}/team\"\n\n[destinations.design-review]\nurl = \"{0}/review\"\n",
+            mock_server.uri()
+        ),
+    )
+    .unwrap();
+    let prompt = |text: &str| {
+        json!({
+            "session_id": "review-session",
+            "cwd": "/home/user/my-project",
+            "prompt": text
+        })
+        .to_string()
+    };
+
+    // 設定にない送信先はONにせず、理由を表示する
+    let output = env
+        .run_hook("toggle", &prompt("/aloud-code:on desing-review"))
+        .await;
+    let stdout: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stdout["decision"], "block");
+    assert_eq!(
+        stdout["reason"],
+        "aloud-code: streaming not started: unknown destination desing-review (configured: default, design-review)"
+    );
+    assert!(!env.state_dir.join("sessions/review-session").exists());
+
+    // 指定した送信先にだけ送る
+    env.run_hook("toggle", &prompt("/aloud-code:on design-review"))
+        .await;
+    env.run_hook("user-prompt", &prompt("Only for reviewers"))
+        .await;
+    let requests = mock_server.received_requests().await.unwrap();
+    let paths: Vec<&str> = requests.iter().map(|r| r.url.path()).collect();
+    assert_eq!(paths, vec!["/review"]);
+
+    // 指定なしで再度ONにするとすべてに戻る
+    env.run_hook("toggle", &prompt("/aloud-code:on")).await;
+    env.run_hook("user-prompt", &prompt("For everyone")).await;
+    assert_eq!(mock_server.received_requests().await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_session_title_and_tags() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[webhook]\nurl = \"{0}/slack\"\n\n[destinations.ingest]\nurl = \"{0}/ingest\"\nformat = \"event\"\n",
+            mock_server.uri()
+        ),
+    )
+    .unwrap();
+    let prompt = |text: &str| {
+        json!({
+            "session_id": "abcdef12-titled",
+            "cwd": "/home/user/my-project",
+            "prompt": text
+        })
+        .to_string()
+    };
+
+    env.run_hook(
+        "toggle",
+        &prompt(r#"/aloud-code:on "Fix flaky auth tests" #backend"#),
+    )
+    .await;
+    env.run_hook("user-prompt", &prompt("Where do we start?"))
+        .await;
+
+    let requests = mock_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    for request in &requests {
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        match request.url.path() {
+            "/slack" => {
+                assert_eq!(
+                    body["username"],
+                    "Fix flaky auth tests · my-project [abcdef12]"
+                );
+                assert_eq!(body["blocks"][1]["elements"][0]["text"], "#backend");
+            }
+            "/ingest" => {
+                assert_eq!(body["title"], "Fix flaky auth tests");
+                assert_eq!(body["tags"], json!(["backend"]));
+            }
+            other => panic!("想定外の送信先: {}", other),
+        }
+    }
+}
+
+/// 別プロセスで送られる告知を、届くまで少し待つ
+async fn wait_for_requests(server: &MockServer, count: usize) -> Vec<wiremock::Request> {
+    for _ in 0..50 {
+        let requests = server.received_requests().await.unwrap();
+        if requests.len() >= count {
+            return requests;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    server.received_requests().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_announce_start_and_stop() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[webhook]\nurl = \"{}\"\n\n[sessions]\nannounce = true\n",
+            mock_server.uri()
+        ),
+    )
+    .unwrap();
+    let prompt = |text: &str| {
+        json!({
+            "session_id": "announce-session",
+            "cwd": "/home/user/my-project",
+            "prompt": text
+        })
+        .to_string()
+    };
+    let text = |request: &wiremock::Request| -> String {
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        body["blocks"][0]["text"]["text"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    let output = env.run_hook("toggle", &prompt("/aloud-code:on")).await;
+    assert!(output.status.success());
+    let requests = wait_for_requests(&mock_server, 1).await;
+    assert_eq!(requests.len(), 1);
+    assert_eq!(text(&requests[0]), ":red_circle: *Streaming started*");
+
+    env.run_hook("user-prompt", &prompt("Hello")).await;
+    env.run_hook("toggle", &prompt("/aloud-code:off")).await;
+    let requests = wait_for_requests(&mock_server, 3).await;
+    assert_eq!(requests.len(), 3);
+    assert!(
+        text(&requests[2])
+            .starts_with(":white_circle: *Streaming stopped* · 1 prompt, 0 responses"),
+        "終了の告知に件数が含まれていない: {}",
+        text(&requests[2])
+    );
+
+    // OFFのままもう一度OFFにしても告知しない
+    env.run_hook("toggle", &prompt("/aloud-code:off")).await;
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    assert_eq!(mock_server.received_requests().await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_public_notice_added_to_context() {
+    let env = TestEnv::new();
+    std::fs::write(
+        &env.config_file,
+        "[webhook]\nurl = \"https://example.com/hook\"\nnotice = \"the #eng Slack channel\"\n",
+    )
+    .unwrap();
+    let prompt = |text: &str| {
+        json!({
+            "session_id": "notice-session",
+            "cwd": "/home/user/my-project",
+            "prompt": text
+        })
+        .to_string()
+    };
+
+    // OFFの間は何も伝えない
+    let output = env.run_hook("toggle", &prompt("Hello")).await;
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "{}");
+
+    env.run_hook("toggle", &prompt("/aloud-code:on")).await;
+    let output = env.run_hook("toggle", &prompt("Hello")).await;
+    let stdout: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let context = stdout["hookSpecificOutput"]["additionalContext"]
+        .as_str()
+        .unwrap();
+    assert_eq!(
+        stdout["hookSpecificOutput"]["hookEventName"],
+        "UserPromptSubmit"
+    );
+    assert!(
+        context.contains("posted to the #eng Slack channel"),
+        "{}",
+        context
+    );
+
+    // プレビュー中は何も送らないので伝えない
+    env.run_hook("toggle", &prompt("/aloud-code:preview")).await;
+    let output = env.run_hook("toggle", &prompt("Hello")).await;
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "{}");
+}
+
+#[tokio::test]
+async fn test_status_and_note_commands() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    // メモはSlack形式の送信先にだけ送るので、失敗させる送信先はイベント形式にする
+    Mock::given(method("POST"))
+        .and(path("/broken"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[webhook]\nurl = \"{0}/slack\"\n\n[destinations.broken]\nurl = \"{0}/broken\"\nformat = \"event\"\n\n\
+             [redact]\npatterns = [\"hunter2\"]\n",
+            mock_server.uri()
+        ),
+    )
+    .unwrap();
+    let slack_requests = || async {
+        let requests = mock_server.received_requests().await.unwrap();
+        requests
+            .into_iter()
+            .filter(|r| r.url.path() == "/slack")
+            .collect::<Vec<_>>()
+    };
+    let prompt = |text: &str| {
+        json!({
+            "session_id": "status-session",
+            "cwd": "/home/user/my-project",
+            "prompt": text
+        })
+        .to_string()
+    };
+    let reason = |output: std::process::Output| -> String {
+        let stdout: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(stdout["decision"], "block");
+        stdout["reason"].as_str().unwrap().to_string()
+    };
+
+    let output = env.run_hook("toggle", &prompt("/aloud-code:status")).await;
+    assert_eq!(reason(output), "aloud-code: streaming off");
+    let output = env
+        .run_hook("toggle", &prompt("/aloud-code:note Too early"))
+        .await;
+    assert_eq!(
+        reason(output),
+        "aloud-code: streaming is off, note not posted"
+    );
+
+    // 送信に失敗した件数がステータスに出る
+    env.run_hook("toggle", &prompt("/aloud-code:on")).await;
+    env.run_hook("user-prompt", &prompt("Hello")).await;
+    let output = env.run_hook("toggle", &prompt("/aloud-code:status")).await;
+    let status = reason(output);
+    assert!(
+        status.starts_with(
+            "aloud-code: streaming on\ndestinations: default, broken\nsent: 1 prompt, 0 responses\nfailures: 1 (last: broken: "
+        ),
+        "{}",
+        status
+    );
+    assert_eq!(slack_requests().await.len(), 1);
+
+    // テキストのないメモは使い方を表示し、プロンプトとしても送らない
+    let output = env.run_hook("toggle", &prompt("/aloud-code:note")).await;
+    assert_eq!(reason(output), "aloud-code: usage: /aloud-code:note <text>");
+    env.run_hook("user-prompt", &prompt("/aloud-code:note"))
+        .await;
+    assert_eq!(slack_requests().await.len(), 1);
+
+    // メモはClaudeに渡さず、マスクしてチャンネルに投稿する
+    let output = env
+        .run_hook("toggle", &prompt("/aloud-code:note Password is hunter2"))
+        .await;
+    assert_eq!(reason(output), "aloud-code: note posted");
+    env.run_hook(
+        "user-prompt",
+        &prompt("/aloud-code:note Password is hunter2"),
+    )
+    .await;
+    let mut requests = slack_requests().await;
+    for _ in 0..50 {
+        if requests.len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        requests = slack_requests().await;
+    }
+    assert_eq!(requests.len(), 2, "メモが届いていない");
+    let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+    let text = body["blocks"][0]["text"]["text"].as_str().unwrap();
+    assert!(text.starts_with(":memo: *Note"), "{}", text);
+    assert!(text.ends_with("Password is [REDACTED]"), "{}", text);
+}
+
+#[tokio::test]
+async fn test_private_prompts_are_not_posted() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&mock_server)
+        .await;
+
+    env.set_webhook_url(&mock_server.uri());
+    let input = |field: &str, text: &str| {
+        let mut input = json!({
+            "session_id": "private-session",
+            "cwd": "/home/user/my-project"
+        });
+        input[field] = json!(text);
+        input.to_string()
+    };
+    let turn = |prompt: &'static str, response: &'static str| {
+        let env = &env;
+        async move {
+            let output = env.run_hook("toggle", &input("prompt", prompt)).await;
+            env.run_hook("user-prompt", &input("prompt", prompt)).await;
+            env.run_hook("stop", &input("last_assistant_message", response))
+                .await;
+            output
+        }
+    };
+    let texts = || async {
+        mock_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| {
+                let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+                body["blocks"][0]["text"]["text"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    env.run_hook("toggle", &input("prompt", "/aloud-code:on"))
+        .await;
+
+    // 接頭辞付きのプロンプトとその応答は送らず、送らないことを表示する
+    let output = turn("!private the key is abc123", "Got it.").await;
+    let stdout: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        stdout["systemMessage"],
+        "aloud-code: this prompt and its response won't be posted"
+    );
+    assert!(texts().await.is_empty());
+
+    // 次のターンからは送る
+    turn("Public question", "Public answer").await;
+    assert_eq!(texts().await.len(), 2);
+
+    // /aloud-code:skip は次の1ターンだけ送らない
+    let output = env
+        .run_hook("toggle", &input("prompt", "/aloud-code:skip"))
+        .await;
+    let stdout: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stdout["decision"], "block");
+    // 非同期のuser-prompt hookが同期のトグルhookより先に動いても送らない
+    env.run_hook("user-prompt", &input("prompt", "Skipped question"))
+        .await;
+    let output = env
+        .run_hook("toggle", &input("prompt", "Skipped question"))
+        .await;
+    let stdout: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        stdout["systemMessage"],
+        "aloud-code: this prompt and its response won't be posted"
+    );
+    env.run_hook("stop", &input("last_assistant_message", "Skipped answer"))
+        .await;
+    turn("Last question", "Last answer").await;
+    let texts = texts().await;
+    assert_eq!(texts.len(), 4);
+    assert!(texts[2].contains("Last question"), "{}", texts[2]);
+    assert!(texts[3].contains("Last answer"), "{}", texts[3]);
+    assert!(texts
+        .iter()
+        .all(|t| !t.contains("abc123") && !t.contains("Skipped")));
+}
+
+#[tokio::test]
+async fn test_note_reports_undeliverable_notes() {
+    let env = TestEnv::new();
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&mock_server)
+        .await;
+
+    let prompt = |text: &str| {
+        json!({
+            "session_id": "note-failure-session",
+            "cwd": "/home/user/my-project",
+            "prompt": text
+        })
+        .to_string()
+    };
+    let reason = |output: std::process::Output| -> String {
+        let stdout: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        stdout["reason"].as_str().unwrap().to_string()
+    };
+
+    // メモを受け取れる送信先がなければ、投稿したことにしない
+    std::fs::write(
+        &env.config_file,
+        format!(
+            "[destinations.ingest]\nurl = \"{}/ingest\"\nformat = \"event\"\n",
+            mock_server.uri()
+        ),
+    )
+    .unwrap();
+    env.run_hook("toggle", &prompt("/aloud-code:on")).await;
+    let output = env.run_hook("toggle", &prompt("/aloud-code:note hi")).await;
+    assert_eq!(
+        reason(output),
+        "aloud-code: note not posted: no destination accepts notes (notes go to Slack-format webhooks without a template)"
+    );
+
+    // 送信に失敗したメモはステータスの失敗に数える
+    env.set_webhook_url(&format!("{}/slack", mock_server.uri()));
+    let output = env.run_hook("toggle", &prompt("/aloud-code:note hi")).await;
+    assert_eq!(reason(output), "aloud-code: note posted");
+    let mut status = String::new();
+    for _ in 0..50 {
+        let output = env.run_hook("toggle", &prompt("/aloud-code:status")).await;
+        status = reason(output);
+        if !status.ends_with("failures: none") {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(
+        status.contains("failures: 1 (last: default: "),
+        "{}",
+        status
+    );
+}